@@ -0,0 +1,280 @@
+// Access log: one line per request, as text or JSON, to stdout or a file.
+//
+// The line is written once the response body has been sent, so it can
+// report the real number of bytes and the time spent streaming them.
+// The time to produce the response also goes to the browser as a
+// Server-Timing header, and into the per-route latency histograms.
+// Log lines are published on /events as well, except those of the
+// routes browsers poll, which would otherwise feed themselves. Live reload
+// polls every second from each open page, so its requests are only counted.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use iron::prelude::*;
+use iron::response::WriteBody;
+use iron::{AfterMiddleware, BeforeMiddleware, headers, typemap};
+use serde_json::json;
+use time::precise_time_ns;
+
+use crate::config::LogFormat;
+use crate::events::{EVENTS_PATH, EventBus};
+use crate::latency::LatencyStats;
+use crate::live_reload::BUILD_EVENTS_PATH;
+use crate::metrics::RequestCounters;
+use crate::request_id::request_id;
+use crate::router::{MatchedRoute, request_path};
+
+// Histogram label for requests that no route matched
+const UNMATCHED_ROUTE: &str = "(unmatched)";
+
+// Routes whose log lines are not published as events
+const UNPUBLISHED_ROUTES: &[&str] = &[EVENTS_PATH, BUILD_EVENTS_PATH];
+
+// Routes whose requests are counted but not logged at all
+const UNLOGGED_ROUTES: &[&str] = &[BUILD_EVENTS_PATH];
+
+// When the request came in, from precise_time_ns()
+pub struct ResponseTime;
+
+impl typemap::Key for ResponseTime {
+    type Value = u64;
+}
+
+// Milliseconds since the request came in
+pub fn elapsed_ms(req: &Request) -> Option<f64> {
+    req.extensions
+        .get::<ResponseTime>()
+        .map(|start| (precise_time_ns() - start) as f64 / 1000000.0)
+}
+
+struct Entry {
+    route: String,
+    request_id: String,
+    timestamp: String,
+    remote_addr: String,
+    method: String,
+    path: String,
+    version: String,
+    status: u16,
+    user_agent: String,
+    // Time until the response was ready to send
+    latency_ms: f64,
+    started_ns: u64,
+}
+
+// Where finished requests end up: counted, then logged
+struct Sink {
+    format: LogFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+    counters: Arc<RequestCounters>,
+    events: Arc<EventBus>,
+}
+
+impl Sink {
+    fn write(&self, entry: &Entry, bytes: usize, error: Option<&io::Error>) {
+        self.counters.record(&entry.route, entry.status, bytes);
+        if UNLOGGED_ROUTES.contains(&entry.route.as_str()) {
+            return;
+        }
+        let total_ms = (precise_time_ns() - entry.started_ns) as f64 / 1000000.0;
+        let line = match self.format {
+            LogFormat::Text => format!(
+                "{} - [{}] [{}] \"{} {} {}\" {} {} \"{}\" {:.3} ms ({:.3} ms total){}",
+                entry.remote_addr,
+                entry.timestamp,
+                entry.request_id,
+                entry.method,
+                entry.path,
+                entry.version,
+                entry.status,
+                bytes,
+                entry.user_agent,
+                entry.latency_ms,
+                total_ms,
+                error.map(|e| format!(" error: {}", e)).unwrap_or_default()
+            ),
+            LogFormat::Json => json!({
+                "request_id": entry.request_id,
+                "timestamp": entry.timestamp,
+                "remote_addr": entry.remote_addr,
+                "method": entry.method,
+                "path": entry.path,
+                "version": entry.version,
+                "status": entry.status,
+                "bytes": bytes,
+                "user_agent": entry.user_agent,
+                "latency_ms": entry.latency_ms,
+                "total_ms": total_ms,
+                "error": error.map(|e| e.to_string()),
+            })
+            .to_string(),
+        };
+        {
+            let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = writeln!(out, "{}", line);
+            let _ = out.flush();
+        }
+        if !UNPUBLISHED_ROUTES.contains(&entry.route.as_str()) {
+            self.events.publish("log", &line);
+        }
+    }
+}
+
+// Counts what goes through to the client
+struct CountingWriter<'a> {
+    inner: &'a mut dyn Write,
+    count: usize,
+}
+
+impl Write for CountingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Stands in for the response body and logs the request once it has been written.
+// If the body is never written (HEAD, 304, dropped connections) the line is logged on drop.
+struct LoggedBody {
+    inner: Option<Box<dyn WriteBody>>,
+    entry: Option<Entry>,
+    sink: Arc<Sink>,
+}
+
+impl WriteBody for LoggedBody {
+    fn write_body(&mut self, res: &mut dyn Write) -> io::Result<()> {
+        let mut counting = CountingWriter {
+            inner: res,
+            count: 0,
+        };
+        let result = match self.inner.as_mut() {
+            Some(body) => body.write_body(&mut counting),
+            None => Ok(()),
+        };
+        if let Some(entry) = self.entry.take() {
+            self.sink
+                .write(&entry, counting.count, result.as_ref().err());
+        }
+        result
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            self.sink.write(&entry, 0, None);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AccessLog {
+    sink: Arc<Sink>,
+    latency: Arc<LatencyStats>,
+}
+
+impl AccessLog {
+    // Log to `file` (appending) if given, to stdout otherwise
+    pub fn new(format: LogFormat, file: Option<&Path>, events: Arc<EventBus>) -> io::Result<Self> {
+        let out: Box<dyn Write + Send> = match file {
+            Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+            None => Box::new(io::stdout()),
+        };
+        Ok(AccessLog {
+            sink: Arc::new(Sink {
+                format,
+                out: Mutex::new(out),
+                counters: Arc::new(RequestCounters::default()),
+                events,
+            }),
+            latency: Arc::new(LatencyStats::default()),
+        })
+    }
+
+    pub fn latency_stats(&self) -> Arc<LatencyStats> {
+        self.latency.clone()
+    }
+
+    pub fn request_counters(&self) -> Arc<RequestCounters> {
+        self.sink.counters.clone()
+    }
+
+    fn entry(&self, req: &Request, res: &Response) -> Entry {
+        let started_ns = req
+            .extensions
+            .get::<ResponseTime>()
+            .copied()
+            .unwrap_or_else(precise_time_ns);
+        let path = match req.url.query() {
+            Some(query) => format!("{}?{}", request_path(req), query),
+            None => request_path(req),
+        };
+        let route = req
+            .extensions
+            .get::<MatchedRoute>()
+            .cloned()
+            .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+        Entry {
+            route,
+            request_id: request_id(req).to_string(),
+            timestamp: time::now_utc().rfc3339().to_string(),
+            remote_addr: req.remote_addr.ip().to_string(),
+            method: req.method.to_string(),
+            path,
+            version: format!("{:?}", req.version),
+            status: res.status.map(|s| s.as_u16()).unwrap_or(404),
+            user_agent: req
+                .headers
+                .get(headers::USER_AGENT)
+                .and_then(|ua| ua.to_str().ok())
+                .unwrap_or("-")
+                .to_string(),
+            latency_ms: (precise_time_ns() - started_ns) as f64 / 1000000.0,
+            started_ns,
+        }
+    }
+
+    fn wrap(&self, req: &Request, mut res: Response) -> Response {
+        let entry = self.entry(req, &res);
+
+        self.latency.record(&entry.route, entry.latency_ms);
+        let server_timing = format!("app;dur={:.3};desc=\"{}\"", entry.latency_ms, entry.route);
+        if let Ok(value) = headers::HeaderValue::from_str(&server_timing) {
+            res.headers
+                .append(headers::HeaderName::from_static("server-timing"), value);
+        }
+
+        res.body = Some(Box::new(LoggedBody {
+            inner: res.body.take(),
+            entry: Some(entry),
+            sink: self.sink.clone(),
+        }));
+        res
+    }
+}
+
+impl BeforeMiddleware for AccessLog {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        req.extensions.insert::<ResponseTime>(precise_time_ns());
+        Ok(())
+    }
+}
+
+impl AfterMiddleware for AccessLog {
+    fn after(&self, req: &mut Request, res: Response) -> IronResult<Response> {
+        Ok(self.wrap(req, res))
+    }
+
+    fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+        err.response = self.wrap(req, err.response);
+        Err(err)
+    }
+}
@@ -0,0 +1,263 @@
+// JSON endpoints for the XHR demos to talk to
+
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+
+use iron::prelude::*;
+use iron::{Method, StatusCode, headers, mime};
+use serde_json::{Map, Value, json};
+
+use crate::access_log::elapsed_ms;
+use crate::router::{query_pairs, query_param, request_path};
+
+// Token accepted by /api/protected
+pub const DEMO_TOKEN: &str = "demo-token";
+
+// Request bodies beyond this are cut off rather than buffered
+const MAX_BODY_BYTES: u64 = 64 * 1024 * 1024;
+
+pub fn json_response(status: StatusCode, body: &Value) -> Response {
+    let content_type = "application/json".parse::<mime::Mime>().unwrap();
+    Response::with((content_type, status, body.to_string()))
+}
+
+pub fn read_body(req: &mut Request) -> IronResult<Vec<u8>> {
+    let mut body = Vec::new();
+    (&mut req.body)
+        .take(MAX_BODY_BYTES)
+        .read_to_end(&mut body)
+        .map_err(|e| IronError::new(e, StatusCode::BAD_REQUEST))?;
+    Ok(body)
+}
+
+fn header_str<'a>(req: &'a Request, name: headers::HeaderName) -> Option<&'a str> {
+    req.headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn query_usize(req: &Request, name: &str, default: usize) -> usize {
+    query_param(req, name)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn headers_json(req: &Request) -> Value {
+    let mut headers = Map::new();
+    for (name, value) in req.headers.iter() {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+        match headers.get_mut(name.as_str()) {
+            // Repeated headers are folded the way HTTP allows
+            Some(Value::String(existing)) => {
+                existing.push_str(", ");
+                existing.push_str(&value);
+            }
+            _ => {
+                headers.insert(name.as_str().to_string(), Value::String(value));
+            }
+        }
+    }
+    Value::Object(headers)
+}
+
+fn query_json(req: &Request) -> Value {
+    let mut query = Map::new();
+    for (k, v) in query_pairs(req) {
+        query.entry(k).or_insert(Value::String(v));
+    }
+    Value::Object(query)
+}
+
+// Describes the request back to the client: GET/POST /api/echo.
+// A POST body is echoed as text, and parsed as well if it is JSON.
+pub fn echo(req: &mut Request) -> IronResult<Response> {
+    let now = time::get_time();
+    let handler_ms = elapsed_ms(req);
+
+    let mut body = json!({
+        "method": req.method.as_str(),
+        "path": request_path(req),
+        "query": query_json(req),
+        "headers": headers_json(req),
+        "remote_addr": req.remote_addr.to_string(),
+        "timing": {
+            "server_time_ms": now.sec * 1000 + (now.nsec / 1000000) as i64,
+            "elapsed_in_server_ms": handler_ms,
+        },
+    });
+    if req.method != Method::GET && req.method != Method::HEAD {
+        let data = read_body(req)?;
+        body["body"] = Value::String(String::from_utf8_lossy(&data).into_owned());
+        body["body_json"] = serde_json::from_slice(&data).unwrap_or(Value::Null);
+    }
+    Ok(json_response(StatusCode::OK, &body))
+}
+
+// Boundary of a multipart body and how many parts it holds, if it is one
+fn multipart_parts(content_type: Option<&str>, data: &[u8]) -> (Option<String>, Option<usize>) {
+    let boundary = content_type.and_then(|ct| {
+        ct.split(';')
+            .filter_map(|param| param.trim().strip_prefix("boundary="))
+            .next()
+            .map(|b| b.trim_matches('"').to_string())
+    });
+    let parts = boundary.as_ref().map(|b| {
+        let delimiter = format!("--{}", b).into_bytes();
+        let delimiters = data
+            .windows(delimiter.len())
+            .filter(|w| *w == &delimiter[..])
+            .count();
+        // The closing delimiter does not start a part
+        delimiters.saturating_sub(1)
+    });
+    (boundary, parts)
+}
+
+// Sink for upload demos: POST /api/upload.
+// Swallows the body and reports what arrived, counting multipart parts if there is a boundary.
+pub fn upload(req: &mut Request) -> IronResult<Response> {
+    let content_type = header_str(req, headers::CONTENT_TYPE).map(|s| s.to_string());
+    let data = read_body(req)?;
+    let (boundary, parts) = multipart_parts(content_type.as_deref(), &data);
+
+    let body = json!({
+        "received_bytes": data.len(),
+        "content_type": content_type,
+        "boundary": boundary,
+        "parts": parts,
+    });
+    Ok(json_response(StatusCode::OK, &body))
+}
+
+// A body that trickles out in chunks, sleeping before each one
+struct SlowBody {
+    remaining: usize,
+    chunk: usize,
+    delay: Duration,
+}
+
+impl Read for SlowBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        thread::sleep(self.delay);
+        let n = buf.len().min(self.chunk).min(self.remaining);
+        for (i, b) in buf[..n].iter_mut().enumerate() {
+            *b = b'a' + ((self.remaining - i) % 26) as u8;
+        }
+        self.remaining -= n;
+        Ok(n)
+    }
+}
+
+// Deliberately slow large download: GET /api/large-file?size=<bytes>&delay_ms=<per 64k chunk>.
+// The defaults take a few seconds, long enough to watch progress or abort.
+pub fn large_file(req: &mut Request) -> IronResult<Response> {
+    let size = query_usize(req, "size", 4 * 1024 * 1024).min(MAX_BODY_BYTES as usize);
+    let delay_ms = query_usize(req, "delay_ms", 50).min(10000);
+
+    let content_type = "application/octet-stream".parse::<mime::Mime>().unwrap();
+    let body: Box<dyn Read + Send> = Box::new(SlowBody {
+        remaining: size,
+        chunk: 64 * 1024,
+        delay: Duration::from_millis(delay_ms as u64),
+    });
+    let mut res = Response::with((content_type, StatusCode::OK, body));
+    // Lets the browser report progress as a fraction
+    res.headers
+        .insert(headers::CONTENT_LENGTH, headers::HeaderValue::from(size));
+    Ok(res)
+}
+
+// Resource behind a bearer token: GET /api/protected.
+// No token is a 401, a wrong one a 403, DEMO_TOKEN a 200.
+pub fn protected(req: &mut Request) -> IronResult<Response> {
+    let token = header_str(req, headers::AUTHORIZATION)
+        .and_then(|auth| auth.strip_prefix("Bearer"))
+        .map(|token| token.trim())
+        .filter(|token| !token.is_empty());
+
+    match token {
+        None => {
+            let mut res = json_response(
+                StatusCode::UNAUTHORIZED,
+                &json!({ "error": "authentication required" }),
+            );
+            res.headers.insert(
+                headers::WWW_AUTHENTICATE,
+                headers::HeaderValue::from_static("Bearer realm=\"mojes-sample\""),
+            );
+            Ok(res)
+        }
+        Some(token) if token != DEMO_TOKEN => Ok(json_response(
+            StatusCode::FORBIDDEN,
+            &json!({ "error": "access forbidden" }),
+        )),
+        Some(_) => Ok(json_response(
+            StatusCode::OK,
+            &json!({ "secret": "the cake is a lie", "authenticated": true }),
+        )),
+    }
+}
+
+// The same resource in the shape each XHR responseType expects:
+// GET /api/resource?type=text|json|blob|arraybuffer|document
+pub fn resource(req: &mut Request) -> IronResult<Response> {
+    let kind = query_param(req, "type").unwrap_or_else(|| "text".to_string());
+
+    let (content_type, body): (&str, Vec<u8>) = match kind.as_str() {
+        "text" | "" => (
+            "text/plain; charset=utf-8",
+            b"Plain text from /api/resource\n".to_vec(),
+        ),
+        "json" => (
+            "application/json",
+            json!({ "type": "json", "items": [1, 2, 3], "nested": { "ok": true } })
+                .to_string()
+                .into_bytes(),
+        ),
+        "blob" | "arraybuffer" => ("application/octet-stream", (0..=255u8).collect()),
+        "document" => (
+            "text/html; charset=utf-8",
+            b"<!DOCTYPE html><html><head><title>Resource</title></head>\
+              <body><p id=\"payload\">Document from /api/resource</p></body></html>"
+                .to_vec(),
+        ),
+        other => {
+            return Ok(json_response(
+                StatusCode::BAD_REQUEST,
+                &json!({ "error": format!("unknown type: {}", other) }),
+            ));
+        }
+    };
+    let content_type = content_type.parse::<mime::Mime>().unwrap();
+    Ok(Response::with((content_type, StatusCode::OK, body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multipart_parts_are_counted() {
+        let body = b"--xyz\r\nContent-Disposition: form-data; name=\"file\"\r\n\r\ndata\r\n\
+            --xyz\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhi\r\n--xyz--\r\n";
+        let (boundary, parts) = multipart_parts(Some("multipart/form-data; boundary=xyz"), body);
+        assert_eq!(boundary.as_deref(), Some("xyz"));
+        assert_eq!(parts, Some(2));
+
+        let (boundary, parts) =
+            multipart_parts(Some("multipart/form-data; boundary=\"xyz\""), body);
+        assert_eq!(boundary.as_deref(), Some("xyz"));
+        assert_eq!(parts, Some(2));
+    }
+
+    #[test]
+    fn no_boundary_no_parts() {
+        assert_eq!(
+            multipart_parts(Some("multipart/form-data"), b"xxxx"),
+            (None, None)
+        );
+        assert_eq!(multipart_parts(None, b"xxxx"), (None, None));
+    }
+}
@@ -0,0 +1,408 @@
+use iron::prelude::*;
+use iron::{Handler, StatusCode, headers, mime};
+
+use mojes::dom::*;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::api::json_response;
+use crate::compression::{self, Encoding};
+use crate::config::BundleFormat;
+use crate::js_graph::JsGraph;
+use crate::{debug_fmt, debug_types, runtime, sourcemap};
+
+pub const APP_JS_PATH: &str = "/app.js";
+pub const APP_JS_MAP_PATH: &str = "/app.js.map";
+pub const RUNTIME_JS_PATH: &str = "/runtime.js";
+pub const BOOTSTRAP_JS_PATH: &str = "/bootstrap.js";
+pub const BUNDLE_REPORT_PATH: &str = "/api/bundle";
+
+const JS_CONTENT_TYPE: &str = "application/javascript; charset=utf-8";
+
+const BUNDLE_HEADER: &str = "
+// Transpiled Rust functions with native JavaScript DOM API calls
+";
+
+// Where a page enters the transpiled code, for the bundle to keep only what
+// these reach; see JsGraph::reachable()
+pub struct EntryPoints {
+    pub roots: Vec<&'static str>,
+    pub dispatchers: &'static [&'static str],
+}
+
+// What went into the bundle, as served at /api/bundle
+#[derive(Debug, Clone, Serialize)]
+pub struct BundleReport {
+    pub tree_shaken: bool,
+    pub entry_points: Vec<&'static str>,
+    pub kept: Vec<String>,
+    pub dropped: Vec<String>,
+}
+
+impl Handler for BundleReport {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        Ok(json_response(StatusCode::OK, &json!(self)))
+    }
+}
+
+pub struct Bundle {
+    pub code: String,
+    pub source_map: String,
+    pub report: BundleReport,
+}
+
+// Transpiled Rust functions, as served at /app.js, in the order js_graph gives them,
+// followed by the type info of the #[js_type] types for the runtime's Debug formatter;
+// and the source map of all that, as served at /app.js.map.
+// Given entry points, only what they reach goes in.
+// As an ES module, the bundle imports the runtime from `runtime_url`
+// and exports every class and function.
+pub fn js_bundle(
+    format: BundleFormat,
+    runtime_url: &str,
+    entry_points: Option<&EntryPoints>,
+) -> Bundle {
+    let all = JsGraph::new(&JS);
+    let kept = match entry_points {
+        Some(entry_points) => all.reachable(&entry_points.roots, entry_points.dispatchers),
+        None => (0..all.items.len()).collect(),
+    };
+    let labels = |keep: bool| -> Vec<String> {
+        let mut labels: Vec<String> = (0..all.items.len())
+            .filter(|i| kept.contains(i) == keep)
+            .map(|i| all.items[i].label())
+            .collect();
+        labels.sort();
+        labels
+    };
+    let report = BundleReport {
+        tree_shaken: entry_points.is_some(),
+        entry_points: entry_points.map(|e| e.roots.clone()).unwrap_or_default(),
+        kept: labels(true),
+        dropped: labels(false),
+    };
+
+    let chunks: Vec<&'static str> = kept.iter().map(|&i| all.items[i].code).collect();
+    let graph = JsGraph::new(&chunks);
+    let chunks = graph.ordered_code();
+    let (head, tail) = match format {
+        BundleFormat::Script => (BUNDLE_HEADER.to_string(), String::new()),
+        BundleFormat::Module => {
+            let exports = graph.exports();
+            let imports: Vec<&str> = runtime::exports()
+                .into_iter()
+                .filter(|name| !exports.contains(name))
+                .collect();
+            (
+                format!(
+                    "{}import {{ {} }} from \"{}\";\n",
+                    BUNDLE_HEADER,
+                    imports.join(", "),
+                    runtime_url
+                ),
+                format!("export {{ {} }};\n", exports.join(", ")),
+            )
+        }
+    };
+    let code = format!(
+        "{}{}\n{}{}//# sourceMappingURL={}\n",
+        head,
+        chunks.join("\n"),
+        debug_fmt::registration_js(&debug_types()),
+        tail,
+        APP_JS_MAP_PATH.trim_start_matches('/')
+    );
+    let source_map = sourcemap::generate(
+        APP_JS_PATH.trim_start_matches('/'),
+        head.matches('\n').count(),
+        &chunks,
+    );
+    Bundle {
+        code,
+        source_map,
+        report,
+    }
+}
+
+// 64-bit FNV-1a: stable across builds and platforms, which is all an ETag needs
+pub fn content_hash(data: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+// URL of the bundle with its hash in it, so the page can let browsers cache it forever
+pub fn fingerprinted_url(path: &str, hash: &str) -> String {
+    format!("{}?v={}", path, hash)
+}
+
+// Does an If-None-Match header with this value cover this ETag?
+fn etag_matches(if_none_match: Option<&str>, etag: &str) -> bool {
+    let Some(value) = if_none_match else {
+        return false;
+    };
+    value.split(',').map(|tag| tag.trim()).any(|tag| {
+        // Weak comparison is fine for GET/HEAD
+        tag == "*" || tag.trim_start_matches("W/") == etag
+    })
+}
+
+// A response body computed once at startup and never changed afterwards,
+// along with its compressed variants, so they are not compressed again per request.
+// The bytes are leaked on purpose: they live as long as the server does,
+// and a &'static slice can be handed to every response without copying.
+#[derive(Clone)]
+pub struct Asset {
+    content_type: mime::Mime,
+    body: &'static [u8],
+    encoded: Vec<(Encoding, &'static [u8])>,
+    hash: String,
+    etag: headers::HeaderValue,
+    // For the compressed variants, whose bytes differ from those the strong ETag is for
+    weak_etag: headers::HeaderValue,
+    fingerprint_query: String,
+}
+
+fn leak(data: Vec<u8>) -> &'static [u8] {
+    Box::leak(data.into_boxed_slice())
+}
+
+impl Asset {
+    pub fn new(content_type: &str, body: String) -> Self {
+        let encoded = compression::precompress(content_type, body.as_bytes())
+            .into_iter()
+            .map(|(encoding, data)| (encoding, leak(data)))
+            .collect();
+        let body = leak(body.into_bytes());
+        let hash = content_hash(body);
+        Asset {
+            content_type: content_type.parse::<mime::Mime>().unwrap(),
+            body,
+            encoded,
+            etag: headers::HeaderValue::from_str(&format!("\"{}\"", hash)).unwrap(),
+            weak_etag: headers::HeaderValue::from_str(&format!("W/\"{}\"", hash)).unwrap(),
+            fingerprint_query: format!("v={}", hash),
+            hash,
+        }
+    }
+
+    // Compressed sizes, for the startup summary
+    pub fn size_report(&self) -> String {
+        compression::size_report(&self.encoded)
+    }
+
+    // The compressed variant to send for Accept-Encoding `accept_encoding`, if any
+    fn variant(&self, accept_encoding: Option<&str>) -> Option<(Encoding, &'static [u8])> {
+        let encoding = compression::negotiate(accept_encoding?)?;
+        self.encoded.iter().find(|(e, _)| *e == encoding).copied()
+    }
+
+    pub fn size(&self) -> usize {
+        self.body.len()
+    }
+
+    pub fn bytes(&self) -> &'static [u8] {
+        self.body
+    }
+
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        fingerprinted_url(path, &self.hash)
+    }
+
+    // Requests whose `query` carries the current fingerprint get a long-lived
+    // immutable cache; everything else has to revalidate.
+    fn cache_control(&self, query: Option<&str>) -> &'static str {
+        let fingerprinted = query
+            .map(|q| q.split('&').any(|kv| kv == self.fingerprint_query))
+            .unwrap_or(false);
+        if fingerprinted {
+            "public, max-age=31536000, immutable"
+        } else {
+            "no-cache"
+        }
+    }
+}
+
+impl Handler for Asset {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let cache_control = self.cache_control(req.url.query());
+
+        let variant = self.variant(
+            req.headers
+                .get(headers::ACCEPT_ENCODING)
+                .and_then(|v| v.to_str().ok()),
+        );
+        let etag = self.etag.to_str().unwrap();
+        let if_none_match = req
+            .headers
+            .get(headers::IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok());
+        let mut res = if etag_matches(if_none_match, etag) {
+            Response::with(StatusCode::NOT_MODIFIED)
+        } else {
+            let body = variant.map(|(_, data)| data).unwrap_or(self.body);
+            let mut res = Response::with((self.content_type.clone(), StatusCode::OK, body));
+            res.headers.insert(
+                headers::CONTENT_LENGTH,
+                headers::HeaderValue::from(body.len()),
+            );
+            if let Some((encoding, _)) = variant {
+                res.headers.insert(
+                    headers::CONTENT_ENCODING,
+                    headers::HeaderValue::from_static(encoding.name()),
+                );
+            }
+            res
+        };
+        let etag = if variant.is_some() {
+            &self.weak_etag
+        } else {
+            &self.etag
+        };
+        res.headers.insert(headers::ETAG, etag.clone());
+        if !self.encoded.is_empty() {
+            res.headers.insert(
+                headers::VARY,
+                headers::HeaderValue::from_static("accept-encoding"),
+            );
+        }
+        res.headers.insert(
+            headers::CACHE_CONTROL,
+            headers::HeaderValue::from_static(cache_control),
+        );
+        Ok(res)
+    }
+}
+
+// The call that starts the transpiled code on a page, once it has loaded
+pub struct Startup {
+    pub function: &'static str,
+    // Arguments, as JS source
+    pub args: String,
+}
+
+impl Startup {
+    fn call(&self) -> String {
+        format!("{}({});", self.function, self.args)
+    }
+}
+
+// What the page needs to know about the other assets
+pub struct PageContext {
+    // Fingerprinted URLs of the runtime prelude and of the bundle
+    pub runtime_url: String,
+    pub app_js_url: String,
+    // The one module to load when the bundle is an ES module, which imports the others
+    pub bootstrap_url: Option<String>,
+    // Statement starting the transpiled code, for the page to run when the bundle is a script
+    pub startup: String,
+}
+
+// Everything the demo serves that does not change at runtime
+pub struct Assets {
+    pub page: Asset,
+    pub runtime: Asset,
+    pub app_js: Asset,
+    pub app_js_map: Asset,
+    // Only for an ES module bundle
+    pub bootstrap: Option<Asset>,
+    pub bundle_report: BundleReport,
+}
+
+impl Assets {
+    // `startup` gets the build ID, the hash of the bundle.
+    // Without entry points, the bundle has every transpiled item.
+    pub fn build(
+        format: BundleFormat,
+        entry_points: Option<&EntryPoints>,
+        startup: impl Fn(&str) -> Startup,
+        render_page: impl Fn(&PageContext) -> String,
+    ) -> Self {
+        let runtime = Asset::new(JS_CONTENT_TYPE, runtime::runtime_js(format));
+        let runtime_url = runtime.url(RUNTIME_JS_PATH);
+        let bundle = js_bundle(format, &runtime_url, entry_points);
+        let app_js = Asset::new(JS_CONTENT_TYPE, bundle.code);
+        let app_js_map = Asset::new("application/json; charset=utf-8", bundle.source_map);
+        let app_js_url = app_js.url(APP_JS_PATH);
+        let startup = startup(app_js.hash());
+        let bootstrap = (format == BundleFormat::Module).then(|| {
+            Asset::new(
+                JS_CONTENT_TYPE,
+                format!(
+                    "// Generated bootstrap: starts the transpiled code on the page\n\
+                     import {{ {} }} from \"{}\";\n\n{}\n",
+                    startup.function,
+                    app_js_url,
+                    startup.call()
+                ),
+            )
+        });
+        let page = Asset::new(
+            "text/html; charset=utf-8",
+            render_page(&PageContext {
+                runtime_url,
+                app_js_url,
+                bootstrap_url: bootstrap.as_ref().map(|b| b.url(BOOTSTRAP_JS_PATH)),
+                startup: startup.call(),
+            }),
+        );
+        Assets {
+            page,
+            runtime,
+            app_js,
+            app_js_map,
+            bootstrap,
+            bundle_report: bundle.report,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_none_match_lists() {
+        let etag = "\"abc\"";
+        assert!(etag_matches(Some("\"abc\""), etag));
+        assert!(etag_matches(Some("W/\"abc\""), etag));
+        assert!(etag_matches(Some("\"old\", W/\"abc\""), etag));
+        assert!(etag_matches(Some("\"old\",\"abc\""), etag));
+        assert!(etag_matches(Some("*"), etag));
+        assert!(!etag_matches(Some("\"old\", W/\"older\""), etag));
+        assert!(!etag_matches(Some("abc"), etag));
+        assert!(!etag_matches(Some(""), etag));
+        assert!(!etag_matches(None, etag));
+    }
+
+    #[test]
+    fn only_the_current_fingerprint_is_immutable() {
+        let asset = Asset::new(JS_CONTENT_TYPE, "console.log(1);\n".to_string());
+        let current = format!("v={}", asset.hash());
+        let immutable = "public, max-age=31536000, immutable";
+        assert_eq!(asset.cache_control(Some(&current)), immutable);
+        assert_eq!(
+            asset.cache_control(Some(&format!("x=1&{}", current))),
+            immutable
+        );
+        assert_eq!(
+            asset.url(APP_JS_PATH),
+            format!("{}?{}", APP_JS_PATH, current)
+        );
+        let stale = format!("v={}", content_hash(b"console.log(0);\n"));
+        assert_eq!(asset.cache_control(Some(&stale)), "no-cache");
+        assert_eq!(
+            asset.cache_control(Some(&format!("{}0", current))),
+            "no-cache"
+        );
+        assert_eq!(asset.cache_control(Some("")), "no-cache");
+        assert_eq!(asset.cache_control(None), "no-cache");
+    }
+}
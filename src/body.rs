@@ -0,0 +1,14 @@
+// Helpers for middleware that needs to look at or replace a response body
+
+use std::io;
+
+use iron::prelude::*;
+
+// Render the response body into bytes, leaving the response without one
+pub fn take_bytes(res: &mut Response) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    if let Some(mut body) = res.body.take() {
+        body.write_body(&mut data)?;
+    }
+    Ok(data)
+}
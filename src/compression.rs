@@ -0,0 +1,311 @@
+// Response compression, negotiated from Accept-Encoding.
+//
+// Only textual bodies (HTML, JS, JSON, source maps...) above a minimum size are
+// compressed; images, octet streams and anything that already carries a
+// Content-Encoding go out untouched. Brotli is available with the `brotli` feature.
+//
+// Bodies known at startup are compressed once then, by precompress(), and served
+// already compressed (see assets::Asset); the middleware only compresses the others.
+
+use std::io::{self, Write};
+
+use flate2::Compression as Level;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use iron::prelude::*;
+use iron::{AfterMiddleware, StatusCode, headers};
+
+use crate::body;
+
+// Below this, the headers cost more than compression saves
+const MIN_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    // In order of preference, when the client likes several equally
+    pub const ALL: &'static [Encoding] = &[
+        #[cfg(feature = "brotli")]
+        Encoding::Brotli,
+        Encoding::Gzip,
+        Encoding::Deflate,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => {
+                let mut out = Vec::new();
+                {
+                    let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+                    encoder.write_all(data)?;
+                }
+                Ok(out)
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            // HTTP's "deflate" is the zlib format, not raw deflate
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Level::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+// Pick the encoding the client weights highest, e.g. from "gzip;q=0.8, br, *;q=0"
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let weights: Vec<(String, f32)> = accept_encoding
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let name = parts.next()?.trim().to_ascii_lowercase();
+            if name.is_empty() {
+                return None;
+            }
+            let q = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((name, q))
+        })
+        .collect();
+    let weight = |name: &str| {
+        weights
+            .iter()
+            .find(|(n, _)| n == name)
+            .or_else(|| weights.iter().find(|(n, _)| n == "*"))
+            .map(|(_, q)| *q)
+            .unwrap_or(0.0)
+    };
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in Encoding::ALL {
+        let q = weight(encoding.name());
+        if q > 0.0 && best.map(|(_, best_q)| q > best_q).unwrap_or(true) {
+            best = Some((*encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+fn compressible(content_type: &str) -> bool {
+    let content_type = content_type.to_ascii_lowercase();
+    content_type.starts_with("text/")
+        || content_type.contains("json")
+        || content_type.contains("javascript")
+        || content_type.contains("xml")
+}
+
+// `data` compressed with every available encoding, if it is worth compressing at all
+pub fn precompress(content_type: &str, data: &[u8]) -> Vec<(Encoding, Vec<u8>)> {
+    if !compressible(content_type) || data.len() < MIN_SIZE {
+        return Vec::new();
+    }
+    Encoding::ALL
+        .iter()
+        .filter_map(|encoding| Some((*encoding, encoding.compress(data).ok()?)))
+        .collect()
+}
+
+// Sizes of the variants precompress() gave, for the startup summary
+pub fn size_report(variants: &[(Encoding, &[u8])]) -> String {
+    if variants.is_empty() {
+        return "not compressed".to_string();
+    }
+    variants
+        .iter()
+        .map(|(encoding, data)| format!("{} {}", encoding.name(), data.len()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn varies_on_encoding(res: &Response) -> bool {
+    res.headers
+        .get_all(headers::VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|name| name.trim().eq_ignore_ascii_case("accept-encoding"))
+}
+
+pub struct Compression;
+
+impl Compression {
+    fn compress(req: &Request, mut res: Response) -> IronResult<Response> {
+        if res.body.is_none()
+            || res.headers.contains_key(headers::CONTENT_ENCODING)
+            || matches!(
+                res.status,
+                Some(StatusCode::NO_CONTENT) | Some(StatusCode::NOT_MODIFIED)
+            )
+        {
+            return Ok(res);
+        }
+        let textual = res
+            .headers
+            .get(headers::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(compressible)
+            .unwrap_or(false);
+        if !textual {
+            return Ok(res);
+        }
+
+        // The body depends on Accept-Encoding from here on, whatever we decide
+        if !varies_on_encoding(&res) {
+            res.headers.append(
+                headers::VARY,
+                headers::HeaderValue::from_static("accept-encoding"),
+            );
+        }
+        let Some(encoding) = req
+            .headers
+            .get(headers::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .and_then(negotiate)
+        else {
+            return Ok(res);
+        };
+
+        let data = body::take_bytes(&mut res)
+            .map_err(|e| IronError::new(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+        if data.len() < MIN_SIZE {
+            res.body = Some(Box::new(data));
+            return Ok(res);
+        }
+        let compressed = encoding
+            .compress(&data)
+            .map_err(|e| IronError::new(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+
+        res.headers.insert(
+            headers::CONTENT_ENCODING,
+            headers::HeaderValue::from_static(encoding.name()),
+        );
+        res.headers.insert(
+            headers::CONTENT_LENGTH,
+            headers::HeaderValue::from(compressed.len()),
+        );
+        // The compressed bytes differ, so a strong ETag no longer holds for them
+        let weak_etag = res
+            .headers
+            .get(headers::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .filter(|etag| !etag.starts_with("W/"))
+            .and_then(|etag| headers::HeaderValue::from_str(&format!("W/{}", etag)).ok());
+        if let Some(etag) = weak_etag {
+            res.headers.insert(headers::ETAG, etag);
+        }
+        res.body = Some(Box::new(compressed));
+        Ok(res)
+    }
+}
+
+impl AfterMiddleware for Compression {
+    fn after(&self, req: &mut Request, res: Response) -> IronResult<Response> {
+        Compression::compress(req, res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highest_weight_wins() {
+        assert_eq!(negotiate("gzip, deflate"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate, gzip;q=0.5"), Some(Encoding::Deflate));
+        assert_eq!(
+            negotiate("gzip;q=0.2, deflate;q=0.8"),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(negotiate(" GZip ; q=0.9 "), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn zero_weight_refuses() {
+        assert_eq!(negotiate("gzip;q=0, deflate"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("gzip;q=0, deflate;q=0"), None);
+        assert_eq!(negotiate("*;q=0"), None);
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[test]
+    fn wildcard_covers_unnamed_encodings() {
+        assert_eq!(negotiate("*"), Some(Encoding::ALL[0]));
+        assert_eq!(negotiate("*;q=0.1, deflate;q=0.5"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("deflate;q=0.5, *;q=0.9"), Some(Encoding::ALL[0]));
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn brotli_preferred_on_ties() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("br;q=0.5, gzip"), Some(Encoding::Gzip));
+    }
+
+    #[cfg(not(feature = "brotli"))]
+    #[test]
+    fn brotli_ignored_without_feature() {
+        assert_eq!(negotiate("br, gzip;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br"), None);
+    }
+
+    #[test]
+    fn compressed_bodies_round_trip() {
+        use flate2::read::{GzDecoder, ZlibDecoder};
+        use std::io::Read;
+
+        let data = "console.log(\"hello\");\n".repeat(100).into_bytes();
+        let mut out = Vec::new();
+        GzDecoder::new(&Encoding::Gzip.compress(&data).unwrap()[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+        out.clear();
+        ZlibDecoder::new(&Encoding::Deflate.compress(&data).unwrap()[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn only_large_text_is_precompressed() {
+        let script = "console.log(\"hello\");\n".repeat(100);
+        let variants = precompress("application/javascript", script.as_bytes());
+        let encodings: Vec<Encoding> = variants.iter().map(|(encoding, _)| *encoding).collect();
+        assert_eq!(encodings, Encoding::ALL);
+        assert!(variants.iter().all(|(_, data)| data.len() < script.len()));
+        assert!(precompress("application/javascript", b"short").is_empty());
+        assert!(precompress("image/png", script.as_bytes()).is_empty());
+    }
+
+    #[test]
+    fn only_text_is_compressible() {
+        assert!(compressible("text/html; charset=utf-8"));
+        assert!(compressible("application/json"));
+        assert!(compressible("application/javascript"));
+        assert!(compressible("Application/XML"));
+        assert!(!compressible("image/png"));
+        assert!(!compressible("application/octet-stream"));
+    }
+}
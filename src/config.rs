@@ -0,0 +1,527 @@
+// Server configuration: defaults, overridden by an optional TOML file,
+// overridden in turn by command-line flags.
+
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+
+use crate::faults::{self, FaultRule};
+
+// Fault rules are picked up from here when no other file is given
+const DEFAULT_FAULTS_FILE: &str = "faults.toml";
+
+// The sections of the demo page, which can be turned on and off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DemoGroup {
+    Basic,
+    Xhr,
+    Dom,
+    Style,
+    Events,
+    Timers,
+    Navigation,
+    Forms,
+    Storage,
+    Console,
+    Push,
+}
+
+impl DemoGroup {
+    pub const ALL: &'static [DemoGroup] = &[
+        DemoGroup::Basic,
+        DemoGroup::Xhr,
+        DemoGroup::Dom,
+        DemoGroup::Style,
+        DemoGroup::Events,
+        DemoGroup::Timers,
+        DemoGroup::Navigation,
+        DemoGroup::Forms,
+        DemoGroup::Storage,
+        DemoGroup::Console,
+        DemoGroup::Push,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BundleFormat {
+    // A classic script, defining the transpiled functions as globals
+    #[default]
+    Script,
+    // An ES module exporting them, started by a generated bootstrap module
+    Module,
+}
+
+// The [cors] table
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    // Origins that may call the server cross-origin; "*" for any, though only
+    // the origins listed by name may send credentials.
+    // Empty means the origins the demo page itself is served from.
+    pub allowed_origins: Vec<String>,
+    // Request headers a preflight may ask for
+    pub allowed_headers: Vec<String>,
+    // Whether cookies and Authorization may be sent cross-origin
+    pub allow_credentials: bool,
+    // How long browsers may cache a preflight answer
+    pub max_age_secs: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allowed_headers: vec![
+                "Authorization".to_string(),
+                "Content-Type".to_string(),
+                "X-Request-Id".to_string(),
+            ],
+            allow_credentials: true,
+            max_age_secs: 600,
+        }
+    }
+}
+
+// The [security_headers] table; an empty value leaves that header out
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityHeadersConfig {
+    pub frame_options: String,
+    pub referrer_policy: String,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        SecurityHeadersConfig {
+            frame_options: "DENY".to_string(),
+            referrer_policy: "strict-origin-when-cross-origin".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+#[command(about = "Interactive test server for the mojes Rust-to-JS transpiler")]
+struct Cli {
+    /// TOML file with any of the settings below; command-line flags take precedence
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Address to listen on [default: localhost]
+    #[arg(short, long)]
+    bind: Option<String>,
+
+    /// Port to listen on [default: 3000]
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    /// Number of worker threads [default: chosen by the server]
+    #[arg(short, long)]
+    threads: Option<NonZeroUsize>,
+
+    /// Comma-separated demo groups to show on the page [default: all]
+    #[arg(long, value_enum, value_delimiter = ',')]
+    demos: Option<Vec<DemoGroup>>,
+
+    /// Format of the request log lines [default: text]
+    #[arg(long, value_enum)]
+    log_format: Option<LogFormat>,
+
+    /// Append the access log to this file instead of printing it
+    #[arg(long)]
+    access_log: Option<PathBuf>,
+
+    /// TOML file with [[fault]] rules [default: faults.toml, if present]
+    #[arg(long)]
+    faults: Option<PathBuf>,
+
+    /// Reload open pages when the server restarts with a different JS bundle
+    #[arg(long, overrides_with = "no_dev")]
+    dev: bool,
+
+    /// Turn off --dev, also when the config file sets `dev = true`
+    #[arg(long, overrides_with = "dev")]
+    no_dev: bool,
+
+    /// Serve the JS bundle as a classic script or as an ES module with named exports [default: script]
+    #[arg(long, value_enum)]
+    bundle_format: Option<BundleFormat>,
+
+    /// Ship every transpiled item, not just those the page's demos can reach
+    #[arg(long, overrides_with = "no_full_bundle")]
+    full_bundle: bool,
+
+    /// Turn off --full-bundle, also when the config file sets `full_bundle = true`
+    #[arg(long, overrides_with = "full_bundle")]
+    no_full_bundle: bool,
+
+    /// Second port to serve on, as another origin for the CORS demos [default: port + 1]
+    #[arg(long)]
+    second_origin_port: Option<u16>,
+
+    /// Port of the WebSocket chat [default: port + 2]
+    #[arg(long)]
+    websocket_port: Option<u16>,
+
+    /// Origin allowed to make cross-origin requests; can be repeated [default: the page's own]
+    #[arg(long = "cors-origin")]
+    cors_origins: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: String,
+    pub port: u16,
+    pub threads: Option<NonZeroUsize>,
+    pub demos: Vec<DemoGroup>,
+    pub log_format: LogFormat,
+    // Access log goes to stdout unless this is set
+    pub access_log: Option<PathBuf>,
+    // File with more fault rules
+    pub faults: Option<PathBuf>,
+    // Fault rules given inline, as [[fault]] tables
+    pub fault: Vec<FaultRule>,
+    // Live reload of the demo page
+    pub dev: bool,
+    pub bundle_format: BundleFormat,
+    // No tree shaking of the bundle
+    pub full_bundle: bool,
+    // Defaults to the port after `port`
+    pub second_origin_port: Option<u16>,
+    // Defaults to two ports after `port`
+    pub websocket_port: Option<u16>,
+    pub cors: CorsConfig,
+    pub security_headers: SecurityHeadersConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: "localhost".to_string(),
+            port: 3000,
+            threads: None,
+            demos: DemoGroup::ALL.to_vec(),
+            log_format: LogFormat::Text,
+            access_log: None,
+            faults: None,
+            fault: Vec::new(),
+            dev: false,
+            bundle_format: BundleFormat::Script,
+            full_bundle: false,
+            second_origin_port: None,
+            websocket_port: None,
+            cors: CorsConfig::default(),
+            security_headers: SecurityHeadersConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    // Build the configuration from the command line and the file it names
+    pub fn load() -> Result<Config, String> {
+        let cli = Cli::parse();
+        let mut config = match &cli.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        config.apply_cli(cli);
+        config.check_ports()?;
+        Ok(config)
+    }
+
+    // The settings given on the command line take precedence
+    fn apply_cli(&mut self, cli: Cli) {
+        if let Some(bind) = cli.bind {
+            self.bind = bind;
+        }
+        if let Some(port) = cli.port {
+            self.port = port;
+        }
+        if let Some(threads) = cli.threads {
+            self.threads = Some(threads);
+        }
+        if let Some(demos) = cli.demos {
+            self.demos = demos;
+        }
+        if let Some(log_format) = cli.log_format {
+            self.log_format = log_format;
+        }
+        if let Some(access_log) = cli.access_log {
+            self.access_log = Some(access_log);
+        }
+        if let Some(faults) = cli.faults {
+            self.faults = Some(faults);
+        }
+        if cli.dev {
+            self.dev = true;
+        }
+        if cli.no_dev {
+            self.dev = false;
+        }
+        if let Some(bundle_format) = cli.bundle_format {
+            self.bundle_format = bundle_format;
+        }
+        if cli.full_bundle {
+            self.full_bundle = true;
+        }
+        if cli.no_full_bundle {
+            self.full_bundle = false;
+        }
+        if let Some(port) = cli.second_origin_port {
+            self.second_origin_port = Some(port);
+        }
+        if let Some(port) = cli.websocket_port {
+            self.websocket_port = Some(port);
+        }
+        if !cli.cors_origins.is_empty() {
+            self.cors.allowed_origins = cli.cors_origins;
+        }
+    }
+
+    // The ports after `port` that the second origin and the WebSocket chat default to
+    // have to exist, and the three listeners need a port each
+    fn check_ports(&self) -> Result<(), String> {
+        for (name, port, flag, offset) in [
+            (
+                "second origin",
+                self.second_origin_port,
+                "--second-origin-port",
+                1,
+            ),
+            ("WebSocket chat", self.websocket_port, "--websocket-port", 2),
+        ] {
+            if port.is_none() && self.port.checked_add(offset).is_none() {
+                return Err(format!(
+                    "port {} leaves no room for the {} on port + {}; set {}",
+                    self.port, name, offset, flag
+                ));
+            }
+        }
+        let listeners = [
+            ("server", self.port),
+            ("second origin", self.second_origin_port()),
+            ("WebSocket chat", self.websocket_port()),
+        ];
+        for (i, (name, port)) in listeners.iter().enumerate() {
+            if let Some((other, _)) = listeners[..i].iter().find(|(_, p)| p == port) {
+                return Err(format!(
+                    "the {} and the {} are both set to port {}",
+                    other, name, port
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn from_file(path: &Path) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("cannot parse {}: {}", path.display(), e))
+    }
+
+    // IPv6 addresses need brackets once a port is attached
+    fn host_for_url(host: &str) -> String {
+        if host.contains(':') && !host.starts_with('[') {
+            format!("[{}]", host)
+        } else {
+            host.to_string()
+        }
+    }
+
+    pub fn listen_addr(&self) -> String {
+        format!("{}:{}", Config::host_for_url(&self.bind), self.port)
+    }
+
+    fn url_with_port(&self, port: u16) -> String {
+        let host = match self.bind.as_str() {
+            "0.0.0.0" | "::" | "[::]" => "localhost",
+            bind => bind,
+        };
+        format!("http://{}:{}", Config::host_for_url(host), port)
+    }
+
+    // Where a browser on this machine reaches the server
+    pub fn base_url(&self) -> String {
+        self.url_with_port(self.port)
+    }
+
+    pub fn second_origin_url(&self) -> String {
+        self.url_with_port(self.second_origin_port())
+    }
+
+    // `port` plus `offset`; check_ports() has made sure it exists
+    fn port_after(&self, offset: u16) -> u16 {
+        self.port.saturating_add(offset)
+    }
+
+    pub fn second_origin_port(&self) -> u16 {
+        self.second_origin_port
+            .unwrap_or_else(|| self.port_after(1))
+    }
+
+    pub fn second_origin_listen_addr(&self) -> String {
+        format!(
+            "{}:{}",
+            Config::host_for_url(&self.bind),
+            self.second_origin_port()
+        )
+    }
+
+    pub fn websocket_port(&self) -> u16 {
+        self.websocket_port.unwrap_or_else(|| self.port_after(2))
+    }
+
+    pub fn websocket_listen_addr(&self) -> String {
+        format!(
+            "{}:{}",
+            Config::host_for_url(&self.bind),
+            self.websocket_port()
+        )
+    }
+
+    pub fn websocket_url(&self) -> String {
+        self.url_with_port(self.websocket_port())
+            .replacen("http://", "ws://", 1)
+    }
+
+    // The configured CORS origins, or else every way a browser on this
+    // machine may have reached the demo page
+    pub fn cors_origins(&self) -> Vec<String> {
+        if !self.cors.allowed_origins.is_empty() {
+            return self.cors.allowed_origins.clone();
+        }
+        let mut origins = vec![self.base_url()];
+        for host in ["localhost", "127.0.0.1", "[::1]"] {
+            let origin = format!("http://{}:{}", host, self.port);
+            if !origins.contains(&origin) {
+                origins.push(origin);
+            }
+        }
+        origins
+    }
+
+    // Inline rules first, then those from the faults file
+    pub fn fault_rules(&self) -> Result<Vec<FaultRule>, String> {
+        let mut rules = self.fault.clone();
+        let default_file = Path::new(DEFAULT_FAULTS_FILE);
+        let file = match &self.faults {
+            Some(path) => Some(path.as_path()),
+            None if default_file.exists() => Some(default_file),
+            None => None,
+        };
+        if let Some(file) = file {
+            rules.extend(faults::load_rules(file)?);
+        }
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_args(mut config: Config, args: &[&str]) -> Config {
+        let args = std::iter::once("irontest").chain(args.iter().copied());
+        config.apply_cli(Cli::try_parse_from(args).unwrap());
+        config
+    }
+
+    #[test]
+    fn zero_threads_are_refused() {
+        let args = |threads| ["irontest", "--threads", threads];
+        assert!(Cli::try_parse_from(args("0")).is_err());
+        let config = with_args(Config::default(), &["--threads", "4"]);
+        assert_eq!(config.threads, NonZeroUsize::new(4));
+        assert!(toml::from_str::<Config>("threads = 0").is_err());
+        let config: Config = toml::from_str("threads = 2").unwrap();
+        assert_eq!(config.threads, NonZeroUsize::new(2));
+    }
+
+    #[test]
+    fn flags_turn_file_settings_off() {
+        let from_file = || Config {
+            dev: true,
+            full_bundle: true,
+            ..Config::default()
+        };
+        let config = with_args(from_file(), &[]);
+        assert!(config.dev && config.full_bundle);
+        let config = with_args(from_file(), &["--no-dev", "--no-full-bundle"]);
+        assert!(!config.dev && !config.full_bundle);
+        // The last of a pair wins
+        let config = with_args(Config::default(), &["--no-dev", "--dev"]);
+        assert!(config.dev);
+        let config = with_args(Config::default(), &["--full-bundle", "--no-full-bundle"]);
+        assert!(!config.full_bundle);
+    }
+
+    #[test]
+    fn ports_default_to_following_port() {
+        let config = Config {
+            port: 8000,
+            ..Config::default()
+        };
+        assert_eq!(config.check_ports(), Ok(()));
+        assert_eq!(config.second_origin_port(), 8001);
+        assert_eq!(config.websocket_port(), 8002);
+    }
+
+    #[test]
+    fn default_ports_must_not_overflow() {
+        let config = Config {
+            port: 65535,
+            ..Config::default()
+        };
+        assert!(
+            config
+                .check_ports()
+                .unwrap_err()
+                .contains("--second-origin-port")
+        );
+
+        let config = Config {
+            port: 65534,
+            second_origin_port: Some(4000),
+            ..Config::default()
+        };
+        assert!(
+            config
+                .check_ports()
+                .unwrap_err()
+                .contains("--websocket-port")
+        );
+
+        let config = Config {
+            port: 65535,
+            second_origin_port: Some(4000),
+            websocket_port: Some(4001),
+            ..Config::default()
+        };
+        assert_eq!(config.check_ports(), Ok(()));
+    }
+
+    #[test]
+    fn listeners_need_distinct_ports() {
+        let config = Config {
+            port: 3000,
+            websocket_port: Some(3001),
+            ..Config::default()
+        };
+        assert_eq!(
+            config.check_ports(),
+            Err("the second origin and the WebSocket chat are both set to port 3001".to_string())
+        );
+    }
+}
@@ -0,0 +1,149 @@
+// Content-Security-Policy for the demo page.
+//
+// The page is rendered once, with NONCE_PLACEHOLDER wherever a <script> tag
+// needs a nonce; every response gets a fresh nonce filled in, and a policy
+// that only runs scripts carrying it. No inline event handlers are allowed,
+// so the buttons are wired up by the transpiled code (see wire_demo_buttons).
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use iron::prelude::*;
+use iron::{Handler, StatusCode, headers, mime};
+
+use crate::assets::Asset;
+
+pub const NONCE_PLACEHOLDER: &str = "__CSP_NONCE__";
+
+static NONCE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// 128 unpredictable bits, hex-encoded. RandomState is seeded from the OS,
+// which spares us a dependency on a random number crate for this.
+fn new_nonce() -> String {
+    let n = NONCE_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let mut words = [0u64; 2];
+    for (i, word) in words.iter_mut().enumerate() {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(n);
+        hasher.write_usize(i);
+        hasher.write_u64(time::precise_time_ns());
+        *word = hasher.finish();
+    }
+    format!("{:016x}{:016x}", words[0], words[1])
+}
+
+// Scripts need the nonce, and 'strict-dynamic' lets them load what they need.
+// Styles stay open to inline use: the style demo sets a style attribute.
+fn policy(nonce: &str, connect_src: &str) -> String {
+    format!(
+        "default-src 'self'; \
+         script-src 'nonce-{nonce}' 'strict-dynamic'; \
+         connect-src 'self'{connect_src}; \
+         style-src 'self' 'unsafe-inline'; \
+         object-src 'none'; \
+         base-uri 'none'; \
+         form-action 'self'; \
+         frame-ancestors 'none'"
+    )
+}
+
+// Serves a page with a new nonce and a matching policy on every response.
+// It can never be cached, since the nonce would then be reused.
+pub struct NoncedPage {
+    content_type: mime::Mime,
+    // The page, split at each NONCE_PLACEHOLDER
+    parts: Vec<&'static str>,
+    // Sources besides 'self' that scripts may connect to, each with a leading space
+    connect_src: String,
+}
+
+impl NoncedPage {
+    pub fn new(page: &Asset) -> Self {
+        let text = std::str::from_utf8(page.bytes()).expect("page is UTF-8");
+        NoncedPage {
+            content_type: "text/html; charset=utf-8".parse::<mime::Mime>().unwrap(),
+            parts: text.split(NONCE_PLACEHOLDER).collect(),
+            connect_src: String::new(),
+        }
+    }
+
+    // Let the page's scripts talk to `source` too, e.g. "http://*:3001"
+    pub fn allow_connect(mut self, source: &str) -> Self {
+        self.connect_src.push(' ');
+        self.connect_src.push_str(source);
+        self
+    }
+}
+
+impl NoncedPage {
+    fn respond(&self) -> Response {
+        let nonce = new_nonce();
+        let body = self.parts.join(&nonce);
+        let mut res = Response::with((self.content_type.clone(), StatusCode::OK, body));
+        if let Ok(value) = headers::HeaderValue::from_str(&policy(&nonce, &self.connect_src)) {
+            res.headers.insert(headers::CONTENT_SECURITY_POLICY, value);
+        }
+        res.headers.insert(
+            headers::CACHE_CONTROL,
+            headers::HeaderValue::from_static("no-store"),
+        );
+        res
+    }
+}
+
+impl Handler for NoncedPage {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        Ok(self.respond())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iron::response::WriteBody;
+
+    use super::*;
+
+    // The nonce the policy allows, and the page as sent
+    fn nonce_and_body(page: &NoncedPage) -> (String, String) {
+        let mut res = page.respond();
+        let policy = res.headers[headers::CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let script_src = policy
+            .split(';')
+            .map(|directive| directive.trim())
+            .find(|directive| directive.starts_with("script-src "))
+            .unwrap();
+        assert!(!script_src.contains("'unsafe-inline'"), "{}", policy);
+        let nonce = script_src
+            .split_whitespace()
+            .find_map(|source| source.strip_prefix("'nonce-"))
+            .and_then(|source| source.strip_suffix('\''))
+            .unwrap()
+            .to_string();
+        let mut body = Vec::new();
+        res.body.take().unwrap().write_body(&mut body).unwrap();
+        (nonce, String::from_utf8(body).unwrap())
+    }
+
+    #[test]
+    fn every_response_gets_its_own_nonce() {
+        let page = NoncedPage::new(&Asset::new(
+            "text/html; charset=utf-8",
+            format!(
+                "<script nonce=\"{0}\" src=\"/app.js\"></script><script nonce=\"{0}\">start();</script>",
+                NONCE_PLACEHOLDER
+            ),
+        ));
+        let (first, first_body) = nonce_and_body(&page);
+        let (second, second_body) = nonce_and_body(&page);
+        assert_ne!(first, second);
+        for (nonce, body) in [(first, first_body), (second, second_body)] {
+            assert_eq!(nonce.len(), 32);
+            assert!(!body.contains(NONCE_PLACEHOLDER));
+            assert_eq!(body.matches(&format!("nonce=\"{}\"", nonce)).count(), 2);
+        }
+    }
+}
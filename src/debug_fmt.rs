@@ -0,0 +1,412 @@
+// Type info for the runtime's Debug formatter (debug_repr in runtime.js).
+//
+// A JS value does not say whether 30 is a u32 or an f64, or whether a field is an
+// Option, so the #[js_type] types are described here as well and registered with
+// the runtime by the bundle. The descriptions come from the types themselves: a
+// type declared inside debug_type! {} gets a `debug_type()` built from its fields.
+// The tests below run the formatter under node against native `{:?}` and `{:#?}`;
+// the parity check (GET /api/debug-parity and the "Debug parity" demo) does the
+// same in the browser, with the transpiled code.
+
+use iron::StatusCode;
+use iron::prelude::*;
+use serde_json::{Value, json};
+
+use crate::api::json_response;
+
+pub const PARITY_PATH: &str = "/api/debug-parity";
+
+#[derive(Debug, Clone)]
+pub enum Ty {
+    Bool,
+    // Any integer type
+    Int,
+    // f32 or f64
+    Float,
+    Char,
+    // String or &str
+    Str,
+    Option(Box<Ty>),
+    Vec(Box<Ty>),
+    Tuple(Vec<Ty>),
+    // Another type described to the runtime
+    Named(&'static str),
+}
+
+#[derive(Debug, Clone)]
+pub enum Fields {
+    Unit,
+    Tuple(Vec<Ty>),
+    Named(Vec<(&'static str, Ty)>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Kind {
+    Struct(Fields),
+    Enum(Vec<(&'static str, Fields)>),
+}
+
+#[derive(Debug, Clone)]
+pub struct DebugType {
+    pub name: &'static str,
+    pub kind: Kind,
+}
+
+// How values of a Rust type look to the formatter
+pub trait DebugTy {
+    fn ty() -> Ty;
+}
+
+macro_rules! debug_ty {
+    ($ty:expr => $($t:ty),*) => {
+        $(impl DebugTy for $t {
+            fn ty() -> Ty {
+                $ty
+            }
+        })*
+    };
+}
+
+debug_ty!(Ty::Bool => bool);
+debug_ty!(Ty::Int => i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+debug_ty!(Ty::Float => f32, f64);
+debug_ty!(Ty::Char => char);
+debug_ty!(Ty::Str => String, &str);
+
+impl<T: DebugTy> DebugTy for Option<T> {
+    fn ty() -> Ty {
+        Ty::Option(Box::new(T::ty()))
+    }
+}
+
+impl<T: DebugTy> DebugTy for Vec<T> {
+    fn ty() -> Ty {
+        Ty::Vec(Box::new(T::ty()))
+    }
+}
+
+impl<T: DebugTy> DebugTy for Box<T> {
+    fn ty() -> Ty {
+        T::ty()
+    }
+}
+
+macro_rules! debug_tuple {
+    ($($t:ident),+) => {
+        impl<$($t: DebugTy),+> DebugTy for ($($t,)+) {
+            fn ty() -> Ty {
+                Ty::Tuple(vec![$($t::ty()),+])
+            }
+        }
+    };
+}
+
+debug_tuple!(A);
+debug_tuple!(A, B);
+debug_tuple!(A, B, C);
+debug_tuple!(A, B, C, D);
+
+// Declares a struct with named fields, or an enum, as written inside it, along with
+// `debug_type()` describing it and its DebugTy impl:
+//
+//     debug_type! {
+//         #[js_type]
+//         #[derive(Debug)]
+//         struct Point { x: f64, y: f64 }
+//     }
+macro_rules! debug_type {
+    (@fields) => {
+        $crate::debug_fmt::Fields::Unit
+    };
+    (@fields ($($ty:ty),*)) => {
+        $crate::debug_fmt::Fields::Tuple(vec![
+            $(<$ty as $crate::debug_fmt::DebugTy>::ty()),*
+        ])
+    };
+    (@fields {$($field:ident: $ty:ty),*}) => {
+        $crate::debug_fmt::Fields::Named(vec![
+            $((stringify!($field), <$ty as $crate::debug_fmt::DebugTy>::ty())),*
+        ])
+    };
+    (@named $name:ident) => {
+        impl $crate::debug_fmt::DebugTy for $name {
+            fn ty() -> $crate::debug_fmt::Ty {
+                $crate::debug_fmt::Ty::Named(stringify!($name))
+            }
+        }
+    };
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_attr:meta])* $field_vis:vis $field:ident: $field_ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $($(#[$field_attr])* $field_vis $field: $field_ty),*
+        }
+
+        impl $name {
+            pub fn debug_type() -> $crate::debug_fmt::DebugType {
+                $crate::debug_fmt::DebugType {
+                    name: stringify!($name),
+                    kind: $crate::debug_fmt::Kind::Struct(
+                        $crate::debug_fmt::debug_type!(@fields {$($field: $field_ty),*}),
+                    ),
+                }
+            }
+        }
+
+        $crate::debug_fmt::debug_type!(@named $name);
+    };
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $name:ident {
+            $(
+                $(#[$variant_attr:meta])*
+                $variant:ident
+                $(($($tuple_ty:ty),* $(,)?))?
+                $({$($field:ident: $field_ty:ty),* $(,)?})?
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis enum $name {
+            $(
+                $(#[$variant_attr])*
+                $variant
+                $(($($tuple_ty),*))?
+                $({$($field: $field_ty),*})?
+            ),*
+        }
+
+        impl $name {
+            pub fn debug_type() -> $crate::debug_fmt::DebugType {
+                $crate::debug_fmt::DebugType {
+                    name: stringify!($name),
+                    kind: $crate::debug_fmt::Kind::Enum(vec![$((
+                        stringify!($variant),
+                        $crate::debug_fmt::debug_type!(
+                            @fields $(($($tuple_ty),*))? $({$($field: $field_ty),*})?
+                        ),
+                    )),*]),
+                }
+            }
+        }
+
+        $crate::debug_fmt::debug_type!(@named $name);
+    };
+}
+
+pub(crate) use debug_type;
+
+// The JSON forms below are those documented in runtime.js
+
+fn ty_json(ty: &Ty) -> Value {
+    match ty {
+        Ty::Bool => json!("bool"),
+        Ty::Int => json!("int"),
+        Ty::Float => json!("float"),
+        Ty::Char => json!("char"),
+        Ty::Str => json!("str"),
+        Ty::Option(ty) => json!({ "option": ty_json(ty) }),
+        Ty::Vec(ty) => json!({ "vec": ty_json(ty) }),
+        Ty::Tuple(tys) => json!({ "tuple": tys.iter().map(ty_json).collect::<Vec<_>>() }),
+        Ty::Named(name) => json!({ "type": name }),
+    }
+}
+
+fn fields_json(fields: &Fields) -> Value {
+    match fields {
+        Fields::Unit => Value::Null,
+        Fields::Tuple(tys) => json!({ "tuple": tys.iter().map(ty_json).collect::<Vec<_>>() }),
+        Fields::Named(fields) => json!({
+            "named": fields
+                .iter()
+                .map(|(name, ty)| json!([name, ty_json(ty)]))
+                .collect::<Vec<_>>()
+        }),
+    }
+}
+
+fn kind_json(kind: &Kind) -> Value {
+    match kind {
+        Kind::Struct(fields) => json!({ "struct": fields_json(fields) }),
+        Kind::Enum(variants) => json!({
+            "enum": variants
+                .iter()
+                .map(|(name, fields)| json!([name, fields_json(fields)]))
+                .collect::<Vec<_>>()
+        }),
+    }
+}
+
+// The statement registering `types` with the runtime, for the end of the bundle
+pub fn registration_js(types: &[DebugType]) -> String {
+    let types: serde_json::Map<String, Value> = types
+        .iter()
+        .map(|t| (t.name.to_string(), kind_json(&t.kind)))
+        .collect();
+    format!("registerDebugTypes({});\n", Value::Object(types))
+}
+
+// GET /api/debug-parity: what debug_parity_samples() gives natively,
+// for the page to compare with what its transpiled copy gives
+pub fn parity(_: &mut Request) -> IronResult<Response> {
+    Ok(json_response(
+        StatusCode::OK,
+        &json!(crate::debug_parity_samples()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use super::*;
+    use crate::runtime::tests::run_node;
+    use crate::{ChatMessage, Person};
+
+    // Their fields are only ever printed
+    debug_type! {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        enum Shape {
+            Circle { radius: f64 },
+            Rect(f64, f64),
+            Empty,
+        }
+    }
+
+    debug_type! {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        struct DebugSample {
+            score: f64,
+            maybe: Option<Option<u32>>,
+            pair: (i32, String),
+            initial: char,
+            done: bool,
+            shapes: Vec<Shape>,
+        }
+    }
+
+    fn registration() -> String {
+        let mut types = crate::debug_types();
+        types.extend([Shape::debug_type(), DebugSample::debug_type()]);
+        registration_js(&types)
+    }
+
+    #[test]
+    fn types_are_described_from_their_fields() {
+        assert_eq!(
+            kind_json(&DebugSample::debug_type().kind),
+            json!({ "struct": { "named": [
+                ["score", "float"],
+                ["maybe", { "option": { "option": "int" } }],
+                ["pair", { "tuple": ["int", "str"] }],
+                ["initial", "char"],
+                ["done", "bool"],
+                ["shapes", { "vec": { "type": "Shape" } }],
+            ] } })
+        );
+        assert_eq!(
+            kind_json(&Shape::debug_type().kind),
+            json!({ "enum": [
+                ["Circle", { "named": [["radius", "float"]] }],
+                ["Rect", { "tuple": ["float", "float"] }],
+                ["Empty", null],
+            ] })
+        );
+    }
+
+    // Each value next to the JS the transpiled code holds for it; `make` gives an
+    // instance of a class of that name, as the transpiled constructors do
+    #[test]
+    fn formatter_matches_native_debug() {
+        let samples: Vec<(Box<dyn Debug>, &str)> = vec![
+            (
+                Box::new(Person::new("Ada \"Lovelace\"\n".to_string(), 36)),
+                r#"make("Person", { name: "Ada \"Lovelace\"\n", age: 36 })"#,
+            ),
+            (
+                Box::new(ChatMessage {
+                    kind: "chat".to_string(),
+                    author: Person::new("Grace".to_string(), 45),
+                    text: "tab\there, zero\u{200b}width".to_string(),
+                }),
+                r#"make("ChatMessage", {
+                    kind: "chat",
+                    author: make("Person", { name: "Grace", age: 45 }),
+                    text: "tab\there, zero\u200bwidth",
+                })"#,
+            ),
+            (
+                Box::new(DebugSample {
+                    score: 2.0,
+                    maybe: Some(None),
+                    pair: (1, "two".to_string()),
+                    initial: '\'',
+                    done: true,
+                    shapes: vec![
+                        Shape::Circle { radius: 1.5 },
+                        Shape::Rect(2.0, 1e-7),
+                        Shape::Empty,
+                    ],
+                }),
+                r#"make("DebugSample", {
+                    score: 2,
+                    maybe: Some(null),
+                    pair: [1, "two"],
+                    initial: "'",
+                    done: true,
+                    shapes: [{ Circle: { radius: 1.5 } }, { Rect: [2, 1e-7] }, "Empty"],
+                })"#,
+            ),
+            (
+                Box::new(DebugSample {
+                    score: -0.0,
+                    maybe: Some(Some(7)),
+                    pair: (-3, String::new()),
+                    initial: '\n',
+                    done: false,
+                    shapes: vec![],
+                }),
+                r#"make("DebugSample", {
+                    score: -0,
+                    maybe: 7,
+                    pair: [-3, ""],
+                    initial: "\n",
+                    done: false,
+                    shapes: [],
+                })"#,
+            ),
+            (
+                Box::new(Err::<u32, String>("nope".to_string())),
+                r#"Err("nope")"#,
+            ),
+            (
+                Box::new(vec![0.5, 1e16, 1e-5, 123456.789]),
+                "[0.5, 1e16, 1e-5, 123456.789]",
+            ),
+        ];
+
+        let values: Vec<&str> = samples.iter().map(|(_, js)| *js).collect();
+        let script = format!(
+            "{}const make = (name, fields) => Object.assign(new ({{ [name]: class {{}} }})[name](), fields);\n\
+             const values = [{}];\n\
+             console.log(JSON.stringify(values.map((v) => [debug_repr(v), debug_repr_pretty(v)])));\n",
+            registration(),
+            values.join(",\n")
+        );
+        let Some(output) = run_node(&script) else {
+            return;
+        };
+        let output: Vec<(String, String)> = serde_json::from_str(&output).unwrap();
+        assert_eq!(output.len(), samples.len());
+        for (i, ((value, _), (debug, pretty))) in samples.iter().zip(&output).enumerate() {
+            assert_eq!(debug, &format!("{:?}", value), "sample {}, {{:?}}", i);
+            assert_eq!(pretty, &format!("{:#?}", value), "sample {}, {{:#?}}", i);
+        }
+    }
+}
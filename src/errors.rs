@@ -0,0 +1,148 @@
+// Turns handler panics and IronErrors into proper error responses:
+// a styled HTML page, or a JSON body for /api/* requests.
+// Linked around the router, and once more around the whole chain for
+// whatever the other middleware fails with.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+use iron::prelude::*;
+use iron::{AroundMiddleware, Handler, StatusCode, mime};
+use serde_json::json;
+
+use crate::api::json_response;
+use crate::request_id::request_id;
+use crate::router::request_path;
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn error_page(status: StatusCode, message: &str, error_id: &str) -> Response {
+    let content_type = "text/html; charset=utf-8".parse::<mime::Mime>().unwrap();
+    let reason = status.canonical_reason().unwrap_or("Error");
+    let data = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>{code} {reason}</title>
+    <style>
+        body {{
+            font-family: Arial, sans-serif;
+            margin: 20px;
+            background-color: #f0f0f0;
+        }}
+        .container {{
+            max-width: 800px;
+            margin: 0 auto;
+            background: white;
+            padding: 20px;
+            border-radius: 8px;
+            border-top: 6px solid #dc3545;
+            box-shadow: 0 2px 10px rgba(0,0,0,0.1);
+        }}
+        pre {{
+            background: #f8f9fa;
+            padding: 10px;
+            border-radius: 4px;
+            white-space: pre-wrap;
+        }}
+    </style>
+</head>
+<body>
+    <div class="container">
+        <h1>{code} {reason}</h1>
+        <p>The server failed to handle this request.</p>
+        <pre>{message}</pre>
+        <p>Request ID: <code>{error_id}</code> &mdash; look for it in the server log.</p>
+        <p><a href="/">Back to the demo page</a></p>
+    </div>
+</body>
+</html>"#,
+        code = status.as_u16(),
+        reason = reason,
+        message = html_escape(message),
+        error_id = error_id,
+    );
+    Response::with((content_type, status, data))
+}
+
+// The error body of /api/* requests, also used by the router for paths it does not serve
+pub fn json_error(status: StatusCode, message: &str, error_id: &str) -> Response {
+    json_response(
+        status,
+        &json!({
+            "error": message,
+            "status": status.as_u16(),
+            "request_id": error_id,
+        }),
+    )
+}
+
+fn error_response(req: &Request, status: StatusCode, message: &str, error_id: &str) -> Response {
+    if request_path(req).starts_with("/api/") {
+        json_error(status, message, error_id)
+    } else {
+        error_page(status, message, error_id)
+    }
+}
+
+pub struct CatchErrors;
+
+struct CatchErrorsHandler {
+    handler: Box<dyn Handler>,
+}
+
+impl Handler for CatchErrorsHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.handler.handle(req)));
+        let (status, message) = match result {
+            Ok(Ok(res)) => return Ok(res),
+            Ok(Err(err)) => (
+                err.response
+                    .status
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+                err.error.to_string(),
+            ),
+            Err(payload) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("handler panicked: {}", panic_message(payload.as_ref())),
+            ),
+        };
+
+        let error_id = request_id(req).to_string();
+        eprintln!(
+            "❌ [{}] {} {} failed with {}: {}",
+            error_id,
+            req.method,
+            request_path(req),
+            status.as_u16(),
+            message
+        );
+        Ok(error_response(req, status, &message, &error_id))
+    }
+}
+
+impl AroundMiddleware for CatchErrors {
+    fn around(self, handler: Box<dyn Handler>) -> Box<dyn Handler> {
+        Box::new(CatchErrorsHandler { handler })
+    }
+}
+
+// Deliberately panics, to show the error page: GET /panic and GET /api/panic
+pub fn panic_demo(_: &mut Request) -> IronResult<Response> {
+    panic!("deliberate panic from the panic demo");
+}
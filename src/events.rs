@@ -0,0 +1,216 @@
+// Server push over SSE: GET /events streams ticks, access log lines and
+// custom messages; POST /events publishes its body as a custom message.
+// The demo page shows what it gets, so only pages from the allowed CORS origins
+// may publish.
+//
+// Events are numbered and kept in a ring buffer. A GET answers with the events
+// after the client's Last-Event-ID, waiting a few seconds for one if there
+// are none yet, so the browser's EventSource gets them as they happen.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use iron::prelude::*;
+use iron::{Handler, Method, StatusCode, headers};
+use serde_json::json;
+
+use crate::api::{json_response, read_body};
+use crate::sse::{self, Event};
+
+pub const EVENTS_PATH: &str = "/events";
+
+// Events kept for clients catching up
+const CAPACITY: usize = 256;
+
+// How long a GET waits for something to send. Kept below the shutdown grace period.
+const LONG_POLL: Duration = Duration::from_secs(5);
+
+// How soon the browser comes back for more after a response
+const RETRY_MS: u64 = 250;
+
+// Time between two "tick" events
+const TICK_INTERVAL: Duration = Duration::from_secs(5);
+
+// Longest custom message accepted
+const MAX_MESSAGE_BYTES: usize = 4096;
+
+struct Backlog {
+    // ID of the next event published. IDs start at 1, so that
+    // 0 can tell a subscriber that nothing has happened yet.
+    next_id: u64,
+    events: VecDeque<(u64, Event)>,
+}
+
+impl Default for Backlog {
+    fn default() -> Self {
+        Backlog {
+            next_id: 1,
+            events: VecDeque::new(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct EventBus {
+    backlog: Mutex<Backlog>,
+    published: Condvar,
+}
+
+impl EventBus {
+    pub fn publish(&self, event: &str, data: &str) {
+        let mut backlog = self.backlog.lock().unwrap_or_else(|e| e.into_inner());
+        let id = backlog.next_id;
+        backlog.next_id += 1;
+        backlog.events.push_back((
+            id,
+            Event {
+                event: Some(event.to_string()),
+                id: Some(id.to_string()),
+                data: data.to_string(),
+            },
+        ));
+        if backlog.events.len() > CAPACITY {
+            backlog.events.pop_front();
+        }
+        self.published.notify_all();
+    }
+
+    // Events after `last_id`, waiting up to `timeout` for the first one.
+    // New subscribers, and those left over from before a restart, get a greeting
+    // with the ID to resume from instead.
+    fn after(&self, last_id: Option<u64>, timeout: Duration) -> Vec<Event> {
+        let deadline = Instant::now() + timeout;
+        let mut backlog = self.backlog.lock().unwrap_or_else(|e| e.into_inner());
+        let last_id = match last_id {
+            Some(last_id) if last_id < backlog.next_id => last_id,
+            _ => {
+                return vec![Event {
+                    event: None,
+                    id: Some((backlog.next_id - 1).to_string()),
+                    data: format!("subscribed to {}", EVENTS_PATH),
+                }];
+            }
+        };
+        while backlog.next_id <= last_id + 1 {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            backlog = self
+                .published
+                .wait_timeout(backlog, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        backlog
+            .events
+            .iter()
+            .filter(|(id, _)| *id > last_id)
+            .map(|(_, event)| event.clone())
+            .collect()
+    }
+}
+
+// Publish a "tick" event every TICK_INTERVAL, for as long as the server runs
+pub fn start_ticker(bus: Arc<EventBus>) {
+    thread::spawn(move || {
+        let mut n: u64 = 0;
+        loop {
+            thread::sleep(TICK_INTERVAL);
+            n += 1;
+            bus.publish("tick", &format!("{} {}", n, time::now_utc().rfc3339()));
+        }
+    });
+}
+
+pub struct EventsEndpoint {
+    bus: Arc<EventBus>,
+    allowed_origins: Vec<String>,
+}
+
+// A text/plain or form POST from another site's page needs no preflight, so Cors
+// alone, which only keeps that page from reading the response, would let it publish.
+// Requests without an Origin do not come from a page.
+fn origin_allowed(origin: Option<&str>, allowed_origins: &[String]) -> bool {
+    match origin {
+        Some(origin) => allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == origin),
+        None => true,
+    }
+}
+
+impl EventsEndpoint {
+    pub fn new(bus: Arc<EventBus>, allowed_origins: Vec<String>) -> Self {
+        EventsEndpoint {
+            bus,
+            allowed_origins,
+        }
+    }
+
+    fn subscribe(&self, req: &Request) -> Response {
+        let last_id = req
+            .headers
+            .get("last-event-id")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        sse::event_stream(RETRY_MS, &self.bus.after(last_id, LONG_POLL))
+    }
+
+    fn post_message(&self, req: &mut Request) -> IronResult<Response> {
+        let origin = req
+            .headers
+            .get(headers::ORIGIN)
+            .map(|v| v.to_str().unwrap_or(""));
+        if !origin_allowed(origin, &self.allowed_origins) {
+            return Ok(json_response(
+                StatusCode::FORBIDDEN,
+                &json!({ "error": format!("pages from {} may not publish events", origin.unwrap_or("")) }),
+            ));
+        }
+        let body = read_body(req)?;
+        if body.len() > MAX_MESSAGE_BYTES {
+            return Ok(json_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                &json!({ "error": format!("messages are limited to {} bytes", MAX_MESSAGE_BYTES) }),
+            ));
+        }
+        let message = String::from_utf8_lossy(&body);
+        self.bus.publish("message", &message);
+        Ok(json_response(
+            StatusCode::ACCEPTED,
+            &json!({ "published": message }),
+        ))
+    }
+}
+
+impl Handler for EventsEndpoint {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        if req.method == Method::POST {
+            self.post_message(req)
+        } else {
+            Ok(self.subscribe(req))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_allowed_pages_publish() {
+        let allowed = vec!["http://localhost:8080".to_string()];
+        assert!(origin_allowed(Some("http://localhost:8080"), &allowed));
+        assert!(!origin_allowed(Some("https://evil.example"), &allowed));
+        assert!(!origin_allowed(Some("null"), &allowed));
+        assert!(!origin_allowed(Some(""), &allowed));
+        assert!(origin_allowed(None, &allowed));
+        assert!(origin_allowed(
+            Some("https://evil.example"),
+            &["*".to_string()]
+        ));
+    }
+}
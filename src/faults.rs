@@ -0,0 +1,328 @@
+// Fault injection, to exercise the error, timeout and abort paths of the XHR demos.
+//
+// Faults come from rules in the TOML configuration, one `[[fault]]` table per route:
+//
+//     [[fault]]
+//     path = "/api/*"
+//     delay_ms = 500
+//     status = 503
+//
+// and can be set or overridden per request with query parameters:
+// `fault_delay=<ms>`, `fault_status=<code>`, `fault_truncate=<bytes>`,
+// `fault_throttle=<bytes per second>` and `fault_drop=1`. Delays are capped at
+// MAX_DELAY_MS, so that no request holds a worker thread for long.
+
+use std::io::{self, Read};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use iron::prelude::*;
+use iron::{AfterMiddleware, BeforeMiddleware, StatusCode, headers, typemap};
+use serde::Deserialize;
+
+use crate::body;
+use crate::router::{path_matches, query_pairs, request_path};
+
+// Longest delay injected, as api::large_file caps its own
+const MAX_DELAY_MS: u64 = 10_000;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Fault {
+    // Sleep this long before handling the request
+    pub delay_ms: Option<u64>,
+    // Replace the response status
+    pub status: Option<u16>,
+    // Send only this many bytes of the body, while still announcing the full length
+    pub truncate: Option<usize>,
+    // Trickle the body out at this many bytes per second
+    pub throttle_bps: Option<u64>,
+    // Abandon the connection instead of sending a body
+    pub drop: bool,
+}
+
+impl Fault {
+    fn is_empty(&self) -> bool {
+        self.delay_ms.is_none()
+            && self.status.is_none()
+            && self.truncate.is_none()
+            && self.throttle_bps.is_none()
+            && !self.drop
+    }
+
+    // Query parameters take precedence over whatever a rule configured
+    fn override_from_query(&mut self, query: &[(String, String)]) {
+        fn value<'a>(query: &'a [(String, String)], name: &str) -> Option<&'a str> {
+            query
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+        }
+        fn param<T: std::str::FromStr>(query: &[(String, String)], name: &str) -> Option<T> {
+            value(query, name).and_then(|v| v.parse().ok())
+        }
+        if let Some(v) = param(query, "fault_delay") {
+            self.delay_ms = Some(v);
+        }
+        if let Some(v) = param(query, "fault_status") {
+            self.status = Some(v);
+        }
+        if let Some(v) = param(query, "fault_truncate") {
+            self.truncate = Some(v);
+        }
+        if let Some(v) = param(query, "fault_throttle") {
+            self.throttle_bps = Some(v);
+        }
+        if let Some(v) = value(query, "fault_drop") {
+            self.drop = v == "1" || v == "true";
+        }
+    }
+
+    // The body sent in place of `data`: cut short, trickled out, or both
+    fn body(&self, mut data: Vec<u8>) -> Box<dyn Read + Send> {
+        if let Some(truncate) = self.truncate {
+            data.truncate(truncate);
+        }
+        match self.throttle_bps {
+            Some(bps) => Box::new(ThrottledBody {
+                data: io::Cursor::new(data),
+                bytes_per_tick: (bps as usize / 10).max(1),
+            }),
+            None => Box::new(io::Cursor::new(data)),
+        }
+    }
+}
+
+impl typemap::Key for Fault {
+    type Value = Fault;
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FaultRule {
+    // Route pattern, as for the router: exact, or a prefix ending in "/*"
+    pub path: String,
+    #[serde(flatten)]
+    pub fault: Fault,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FaultFile {
+    #[serde(default)]
+    fault: Vec<FaultRule>,
+}
+
+// Read the [[fault]] rules of a TOML file
+pub fn load_rules(path: &Path) -> Result<Vec<FaultRule>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let file: FaultFile =
+        toml::from_str(&text).map_err(|e| format!("cannot parse {}: {}", path.display(), e))?;
+    Ok(file.fault)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FaultInjector {
+    rules: Vec<FaultRule>,
+}
+
+impl FaultInjector {
+    pub fn new(rules: Vec<FaultRule>) -> Self {
+        FaultInjector { rules }
+    }
+
+    // The fault of the first rule matching `path`
+    fn rule_for(&self, path: &str) -> Fault {
+        self.rules
+            .iter()
+            .find(|rule| path_matches(&rule.path, path))
+            .map(|rule| rule.fault.clone())
+            .unwrap_or_default()
+    }
+
+    fn fault_for(&self, req: &Request) -> Fault {
+        let mut fault = self.rule_for(&request_path(req));
+        fault.override_from_query(&query_pairs(req));
+        fault
+    }
+}
+
+impl BeforeMiddleware for FaultInjector {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let fault = self.fault_for(req);
+        if fault.is_empty() {
+            return Ok(());
+        }
+        if let Some(delay) = fault.delay_ms {
+            thread::sleep(Duration::from_millis(delay.min(MAX_DELAY_MS)));
+        }
+        req.extensions.insert::<Fault>(fault);
+        Ok(())
+    }
+}
+
+// Sends `data` in slices of a tenth of the allowed bandwidth, ten times a second
+struct ThrottledBody {
+    data: io::Cursor<Vec<u8>>,
+    bytes_per_tick: usize,
+}
+
+impl Read for ThrottledBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        thread::sleep(Duration::from_millis(100));
+        let n = buf.len().min(self.bytes_per_tick);
+        self.data.read(&mut buf[..n])
+    }
+}
+
+// A body that fails on the first read, so the server gives up on the connection
+struct DroppedBody;
+
+impl Read for DroppedBody {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "connection dropped by fault injection",
+        ))
+    }
+}
+
+impl AfterMiddleware for FaultInjector {
+    fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+        let Some(fault) = req.extensions.remove::<Fault>() else {
+            return Ok(res);
+        };
+
+        if let Some(status) = fault.status.and_then(|s| StatusCode::from_u16(s).ok()) {
+            res.status = Some(status);
+        }
+
+        if fault.drop {
+            res.body = None;
+            let body: Box<dyn Read + Send> = Box::new(DroppedBody);
+            return Ok(res.set(body));
+        }
+
+        if fault.truncate.is_none() && fault.throttle_bps.is_none() {
+            return Ok(res);
+        }
+
+        let data = body::take_bytes(&mut res)
+            .map_err(|e| IronError::new(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+        // The announced length stays that of the full body, so the client sees a short read
+        res.headers.insert(
+            headers::CONTENT_LENGTH,
+            headers::HeaderValue::from(data.len()),
+        );
+        Ok(res.set(fault.body(data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn rule(path: &str, fault: Fault) -> FaultRule {
+        FaultRule {
+            path: path.to_string(),
+            fault,
+        }
+    }
+
+    #[test]
+    fn query_beats_rule() {
+        let mut fault = Fault {
+            delay_ms: Some(500),
+            status: Some(503),
+            ..Fault::default()
+        };
+        fault.override_from_query(&query(&[
+            ("fault_delay", "20"),
+            ("fault_truncate", "10"),
+            ("fault_throttle", "not a number"),
+        ]));
+        assert_eq!(fault.delay_ms, Some(20));
+        assert_eq!(fault.status, Some(503));
+        assert_eq!(fault.truncate, Some(10));
+        assert_eq!(fault.throttle_bps, None);
+    }
+
+    #[test]
+    fn fault_drop_values() {
+        for (value, drop) in [("1", true), ("true", true), ("0", false), ("yes", false)] {
+            let mut fault = Fault {
+                drop: true,
+                ..Fault::default()
+            };
+            fault.override_from_query(&query(&[("fault_drop", value)]));
+            assert_eq!(fault.drop, drop, "fault_drop={}", value);
+        }
+        let mut fault = Fault::default();
+        fault.override_from_query(&query(&[("fault_delay", "5")]));
+        assert!(!fault.drop);
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let injector = FaultInjector::new(vec![
+            rule(
+                "/api/echo",
+                Fault {
+                    status: Some(500),
+                    ..Fault::default()
+                },
+            ),
+            rule(
+                "/api/*",
+                Fault {
+                    status: Some(503),
+                    ..Fault::default()
+                },
+            ),
+        ]);
+        assert_eq!(injector.rule_for("/api/echo").status, Some(500));
+        assert_eq!(injector.rule_for("/api/upload").status, Some(503));
+        assert!(injector.rule_for("/app.js").is_empty());
+    }
+
+    #[test]
+    fn truncated_body() {
+        let fault = Fault {
+            truncate: Some(4),
+            ..Fault::default()
+        };
+        let mut sent = Vec::new();
+        fault
+            .body(b"hello world".to_vec())
+            .read_to_end(&mut sent)
+            .unwrap();
+        assert_eq!(sent, b"hell");
+    }
+
+    #[test]
+    fn throttled_body_comes_in_slices() {
+        let fault = Fault {
+            throttle_bps: Some(30),
+            truncate: Some(7),
+            ..Fault::default()
+        };
+        let mut body = fault.body(b"hello world".to_vec());
+        let mut buf = [0; 64];
+        let mut slices = Vec::new();
+        loop {
+            let n = body.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            slices.push(String::from_utf8(buf[..n].to_vec()).unwrap());
+        }
+        assert_eq!(slices, ["hel", "lo ", "w"]);
+    }
+}
@@ -0,0 +1,515 @@
+// Dependencies between the entries of the JS slice, and the order they are emitted in.
+//
+// linkme collects the entries in no particular order, which may change from one
+// build to the next, while a class has to be defined before the bundle's top level
+// uses it. An entry defining a class is a type, one adding methods to a class
+// (`Foo.prototype.bar = ...`) an impl, anything else a function, and an entry
+// depends on the entries defining the identifiers its code mentions, outside of
+// comments and string literals.
+//
+// Entries are emitted dependencies first. Of those free to go next, the lowest kind
+// goes first (types, then impls, then functions), then the lowest name, then the
+// lowest text, so the bundle only changes when the transpiled code does.
+// Cycles, such as mutually recursive functions, are broken by emitting the lowest
+// entry that is in one, by the same tie-break, ahead of its dependencies.
+//
+// A page need not ship every entry: reachable() finds those its entry points use,
+// directly or not, for the bundle to leave the rest out. What a dispatcher such as
+// run_demo() may call is read from its Rust source instead, by dispatch_table().
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::sourcemap::{js_defined_names, rust_items, words};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ItemKind {
+    Type,
+    Impl,
+    Function,
+}
+
+#[derive(Debug, Clone)]
+pub struct JsItem {
+    pub code: &'static str,
+    pub kind: ItemKind,
+    // Names defined by the entry, the first of which names the entry itself
+    pub names: Vec<String>,
+    // Indexes of the entries it depends on
+    pub deps: BTreeSet<usize>,
+}
+
+impl JsItem {
+    pub fn name(&self) -> &str {
+        self.names.first().map(|name| name.as_str()).unwrap_or("")
+    }
+
+    // How the entry is called in reports: an impl by the type it adds methods to
+    pub fn label(&self) -> String {
+        match (self.kind, self.name()) {
+            (_, "") => "<unnamed>".to_string(),
+            (ItemKind::Impl, name) => format!("impl {}", name),
+            (_, name) => name.to_string(),
+        }
+    }
+}
+
+// The code with its comments and the text of its string literals blanked out, keeping
+// the `${...}` parts of template literals. Regex literals are taken for code.
+fn code_only(code: &str) -> String {
+    enum State {
+        Code,
+        LineComment,
+        BlockComment,
+        Quoted(char),
+        Template,
+    }
+    let chars: Vec<char> = code.chars().collect();
+    let mut out = String::with_capacity(code.len());
+    let mut state = State::Code;
+    // Open braces within each `${` we are in, innermost last
+    let mut interpolations: Vec<usize> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let mut code_char = false;
+        match state {
+            State::Code => match (c, next) {
+                ('/', Some('/')) => state = State::LineComment,
+                ('/', Some('*')) => {
+                    state = State::BlockComment;
+                    out.push(' ');
+                    i += 1;
+                }
+                ('"' | '\'', _) => state = State::Quoted(c),
+                ('`', _) => state = State::Template,
+                ('{', _) => {
+                    if let Some(open) = interpolations.last_mut() {
+                        *open += 1;
+                    }
+                    code_char = true;
+                }
+                ('}', _) if interpolations.last() == Some(&0) => {
+                    interpolations.pop();
+                    state = State::Template;
+                }
+                ('}', _) => {
+                    if let Some(open) = interpolations.last_mut() {
+                        *open -= 1;
+                    }
+                    code_char = true;
+                }
+                _ => code_char = true,
+            },
+            State::LineComment => {
+                if c == '\n' {
+                    state = State::Code;
+                    code_char = true;
+                }
+            }
+            State::BlockComment => {
+                if c == '*' && next == Some('/') {
+                    state = State::Code;
+                    out.push(' ');
+                    i += 1;
+                }
+            }
+            State::Quoted(quote) => {
+                if c == '\\' {
+                    out.push(' ');
+                    i += 1;
+                } else if c == quote || c == '\n' {
+                    state = State::Code;
+                }
+            }
+            State::Template => {
+                if c == '\\' {
+                    out.push(' ');
+                    i += 1;
+                } else if c == '`' {
+                    state = State::Code;
+                } else if c == '$' && next == Some('{') {
+                    interpolations.push(0);
+                    state = State::Code;
+                    out.push(' ');
+                    i += 1;
+                }
+            }
+        }
+        out.push(if code_char || c == '\n' { c } else { ' ' });
+        i += 1;
+    }
+    out
+}
+
+// The arms of the `match` on a string in the transpiled Rust function `dispatcher`:
+// for each string matched, the identifiers its arm uses, among them the functions
+// it calls. Arms are told apart by their indentation, that of the first one.
+pub fn dispatch_table<'a>(
+    source: &'a str,
+    dispatcher: &str,
+) -> BTreeMap<&'a str, BTreeSet<&'a str>> {
+    let mut table: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    let Some(item) = rust_items(source)
+        .into_iter()
+        .find(|item| item.name == dispatcher && item.attr == "#[to_js]")
+    else {
+        return table;
+    };
+    let mut arm_indent = None;
+    let mut keys: Vec<&str> = Vec::new();
+    for line in source
+        .lines()
+        .skip(item.first_line + 1)
+        .take(item.last_line - item.first_line)
+    {
+        let code = line.trim_start();
+        if code.starts_with("//") {
+            continue;
+        }
+        let indent = line.len() - code.len();
+        let mut body = code;
+        if let Some((pattern, rest)) = code.split_once("=>")
+            && arm_indent.is_none_or(|arm_indent| arm_indent == indent)
+        {
+            let strings: Option<Vec<&str>> = pattern
+                .split('|')
+                .map(|p| p.trim().strip_prefix('"')?.strip_suffix('"'))
+                .collect();
+            match strings {
+                Some(strings) => {
+                    arm_indent = Some(indent);
+                    keys = strings;
+                    body = rest;
+                }
+                None if arm_indent.is_some() => {
+                    keys.clear();
+                    continue;
+                }
+                None => {}
+            }
+        }
+        for key in &keys {
+            table.entry(key).or_default().extend(words(body));
+        }
+    }
+    table
+}
+
+pub struct JsGraph {
+    pub items: Vec<JsItem>,
+}
+
+impl JsGraph {
+    pub fn new(chunks: &[&'static str]) -> Self {
+        let mut items: Vec<JsItem> = chunks
+            .iter()
+            .map(|code| {
+                let defined = js_defined_names(code);
+                let kind = if defined.iter().any(|(_, is_class)| *is_class) {
+                    ItemKind::Type
+                } else if code.contains(".prototype.") {
+                    ItemKind::Impl
+                } else {
+                    ItemKind::Function
+                };
+                let mut names: Vec<String> = Vec::new();
+                for (name, _) in defined {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+                JsItem {
+                    code,
+                    kind,
+                    names,
+                    deps: BTreeSet::new(),
+                }
+            })
+            .collect();
+
+        let mut definers: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        for (i, item) in items.iter().enumerate() {
+            for name in &item.names {
+                definers.entry(name.as_str()).or_default().push(i);
+            }
+        }
+
+        let deps: Vec<BTreeSet<usize>> = items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let mut deps = BTreeSet::new();
+                let code = code_only(item.code);
+                let mentioned: BTreeSet<&str> = words(&code).collect();
+                for name in mentioned {
+                    let Some(found) = definers.get(name) else {
+                        continue;
+                    };
+                    // An impl mentions its own type's name, which it defines too
+                    let own = item.names.iter().any(|n| n == name);
+                    deps.extend(found.iter().copied().filter(|&j| {
+                        j != i
+                            && (!own
+                                || (item.kind == ItemKind::Impl && items[j].kind == ItemKind::Type))
+                    }));
+                }
+                deps
+            })
+            .collect();
+        for (item, deps) in items.iter_mut().zip(deps) {
+            item.deps = deps;
+        }
+
+        JsGraph { items }
+    }
+
+    // Indexes of the entries, in the order they are emitted in
+    pub fn order(&self) -> Vec<usize> {
+        let mut pending: BTreeSet<(ItemKind, &str, &str, usize)> = self
+            .items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (item.kind, item.name(), item.code, i))
+            .collect();
+        let mut emitted = vec![false; self.items.len()];
+        let mut order = Vec::with_capacity(self.items.len());
+        while !pending.is_empty() {
+            // When every entry left waits on another, some of them wait on each other
+            let next = pending
+                .iter()
+                .find(|(_, _, _, i)| self.items[*i].deps.iter().all(|&dep| emitted[dep]))
+                .or_else(|| {
+                    pending
+                        .iter()
+                        .find(|(_, _, _, i)| self.in_cycle(*i, &emitted))
+                })
+                .copied()
+                .expect("entries left waiting on each other form a cycle");
+            pending.remove(&next);
+            emitted[next.3] = true;
+            order.push(next.3);
+        }
+        order
+    }
+
+    // Whether the entries not yet emitted that `start` depends on lead back to it
+    fn in_cycle(&self, start: usize, emitted: &[bool]) -> bool {
+        let mut seen = vec![false; self.items.len()];
+        let mut pending: Vec<usize> = self.items[start].deps.iter().copied().collect();
+        while let Some(i) = pending.pop() {
+            if i == start {
+                return true;
+            }
+            if emitted[i] || seen[i] {
+                continue;
+            }
+            seen[i] = true;
+            pending.extend(self.items[i].deps.iter().copied());
+        }
+        false
+    }
+
+    // Indexes of the entries reachable from the functions named in `roots`, through
+    // what each entry uses. A dispatcher, which calls whichever function it is told to,
+    // is kept without following what it uses: those functions have to be roots themselves.
+    pub fn reachable(&self, roots: &[&str], dispatchers: &[&str]) -> BTreeSet<usize> {
+        let mut reached = BTreeSet::new();
+        let mut pending: Vec<usize> = (0..self.items.len())
+            .filter(|&i| roots.contains(&self.items[i].name()))
+            .collect();
+        while let Some(i) = pending.pop() {
+            if !reached.insert(i) || dispatchers.contains(&self.items[i].name()) {
+                continue;
+            }
+            pending.extend(self.items[i].deps.iter().copied());
+        }
+        reached
+    }
+
+    // Names of the classes and functions, for the ES module form of the bundle to export
+    pub fn exports(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for i in self.order() {
+            let item = &self.items[i];
+            if item.kind != ItemKind::Impl
+                && !item.name().is_empty()
+                && !names.contains(&item.name())
+            {
+                names.push(item.name());
+            }
+        }
+        names
+    }
+
+    // The entries' code, in the order they are emitted in
+    pub fn ordered_code(&self) -> Vec<&'static str> {
+        self.order()
+            .into_iter()
+            .map(|i| self.items[i].code)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(chunks: &[&'static str]) -> JsGraph {
+        JsGraph::new(chunks)
+    }
+
+    fn names(graph: &JsGraph, indexes: impl IntoIterator<Item = usize>) -> Vec<String> {
+        indexes
+            .into_iter()
+            .map(|i| graph.items[i].label())
+            .collect()
+    }
+
+    const POINT: &str = "class Point {\n  constructor(x, y) { this.x = x; this.y = y; }\n}";
+    const POINT_IMPL: &str =
+        "Point.prototype.norm = function() {\n  return hypot(this.x, this.y);\n};";
+    const HYPOT: &str = "function hypot(a, b) {\n  return Math.sqrt(a * a + b * b);\n}";
+    const MAIN: &str = "function main() {\n  console.log(new Point(3, 4).norm());\n}";
+
+    #[test]
+    fn kinds_and_dependencies() {
+        let g = graph(&[MAIN, HYPOT, POINT_IMPL, POINT]);
+        let kinds: Vec<ItemKind> = g.items.iter().map(|item| item.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                ItemKind::Function,
+                ItemKind::Function,
+                ItemKind::Impl,
+                ItemKind::Type
+            ]
+        );
+        // Using a class brings in the methods added to it
+        assert_eq!(g.items[0].deps, BTreeSet::from([2, 3]));
+        assert_eq!(g.items[1].deps, BTreeSet::new());
+        assert_eq!(g.items[2].deps, BTreeSet::from([1, 3]));
+        assert_eq!(g.items[2].label(), "impl Point");
+    }
+
+    #[test]
+    fn dependencies_come_first() {
+        // Types, then impls, then functions, unless a dependency says otherwise
+        let g = graph(&[MAIN, HYPOT, POINT_IMPL, POINT]);
+        assert_eq!(
+            names(&g, g.order()),
+            ["Point", "hypot", "impl Point", "main"]
+        );
+        let g = graph(&[
+            "function zebra() { return 1; }",
+            POINT_IMPL,
+            POINT,
+            "function aardvark() { return zebra(); }",
+            HYPOT,
+        ]);
+        assert_eq!(
+            names(&g, g.order()),
+            ["Point", "hypot", "impl Point", "zebra", "aardvark"]
+        );
+    }
+
+    #[test]
+    fn order_does_not_depend_on_input_order() {
+        let chunks = [MAIN, HYPOT, POINT_IMPL, POINT];
+        let expected = graph(&chunks).ordered_code();
+        let mut reversed = chunks;
+        reversed.reverse();
+        assert_eq!(graph(&reversed).ordered_code(), expected);
+        reversed.rotate_left(1);
+        assert_eq!(graph(&reversed).ordered_code(), expected);
+    }
+
+    #[test]
+    fn cycles_are_broken_by_name() {
+        let even = "function is_even(n) { return n == 0 || is_odd(n - 1); }";
+        let odd = "function is_odd(n) { return n != 0 && is_even(n - 1); }";
+        let user = "function check() { return is_odd(3); }";
+        let g = graph(&[user, odd, even]);
+        assert_eq!(names(&g, g.order()), ["is_even", "is_odd", "check"]);
+        let g = graph(&[even, odd, user]);
+        assert_eq!(names(&g, g.order()), ["is_even", "is_odd", "check"]);
+    }
+
+    #[test]
+    fn strings_and_comments_are_not_dependencies() {
+        let g = graph(&[
+            "function greet() {\n  // calls hypot? no\n  return \"hypot\" + 'main' + `hypot`; /* main */\n}",
+            HYPOT,
+            MAIN,
+        ]);
+        assert_eq!(g.items[0].deps, BTreeSet::new());
+        let g = graph(&[
+            "function show() { return `${hypot(1, `${main()}`)} and hypot`; }",
+            HYPOT,
+            MAIN,
+        ]);
+        assert_eq!(g.items[0].deps, BTreeSet::from([1, 2]));
+        let g = graph(&[
+            "function q() { return \"say \\\"hypot\\\"\" + main(); }",
+            HYPOT,
+            MAIN,
+        ]);
+        assert_eq!(g.items[0].deps, BTreeSet::from([2]));
+    }
+
+    #[test]
+    fn reachable_follows_uses_but_not_dispatchers() {
+        let run_demo = "function run_demo(demo) {\n  switch (demo) { case \"x\": return main(); case \"y\": return hypot(1, 2); }\n}";
+        let unused = "function unused() { return hypot(1, 1); }";
+        let g = graph(&[MAIN, HYPOT, POINT_IMPL, POINT, run_demo, unused]);
+
+        let kept = g.reachable(&["main"], &[]);
+        assert_eq!(names(&g, kept), ["main", "hypot", "impl Point", "Point"]);
+
+        let kept = g.reachable(&["run_demo"], &["run_demo"]);
+        assert_eq!(names(&g, kept), ["run_demo"]);
+
+        let kept = g.reachable(&["run_demo", "unused", "missing"], &["run_demo"]);
+        assert_eq!(names(&g, kept), ["hypot", "run_demo", "unused"]);
+    }
+
+    #[test]
+    fn dispatch_table_from_match_arms() {
+        let source = "\
+#[to_js]
+fn run(demo: &str, arg: Option<String>) {
+    match demo {
+        \"add\" => log(add(1, 2)),
+        \"get\" | \"fetch\" => fetch(&arg.unwrap()),
+        // Spread over lines
+        \"auth\" => login(
+            &format!(\"{}\", arg.unwrap()),
+            match arg {
+                Some(_) => token(),
+                _ => none(),
+            },
+        ),
+        _ => fallback(demo),
+    }
+}
+";
+        let table = dispatch_table(source, "run");
+        assert_eq!(
+            table.keys().copied().collect::<Vec<_>>(),
+            ["add", "auth", "fetch", "get"]
+        );
+        assert_eq!(table["add"], BTreeSet::from(["log", "add"]));
+        assert!(table["get"].contains("fetch") && table["fetch"].contains("fetch"));
+        for name in ["login", "token", "none", "format"] {
+            assert!(table["auth"].contains(name), "{}", name);
+        }
+        assert!(table.values().all(|uses| !uses.contains("fallback")));
+        assert!(dispatch_table(source, "missing").is_empty());
+    }
+
+    #[test]
+    fn exports_leave_impls_out() {
+        let g = graph(&[MAIN, HYPOT, POINT_IMPL, POINT]);
+        assert_eq!(g.exports(), ["Point", "hypot", "main"]);
+    }
+}
@@ -0,0 +1,186 @@
+// Per-route latency histograms, fed by the access log
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use iron::prelude::*;
+use iron::{Handler, StatusCode};
+use serde_json::{Value, json};
+
+use crate::api::json_response;
+
+// Upper bounds of the histogram buckets, in milliseconds; the last bucket is unbounded
+pub const BUCKETS_MS: &[f64] = &[
+    0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0,
+    10000.0,
+];
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    // One count per bucket, plus the overflow bucket
+    counts: Vec<u64>,
+    count: u64,
+    sum_ms: f64,
+    max_ms: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: vec![0; BUCKETS_MS.len() + 1],
+            count: 0,
+            sum_ms: 0.0,
+            max_ms: 0.0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, ms: f64) {
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += ms;
+        self.max_ms = self.max_ms.max(ms);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum_ms(&self) -> f64 {
+        self.sum_ms
+    }
+
+    // (upper bound, number of observations at or below it), as Prometheus wants them
+    pub fn cumulative(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        BUCKETS_MS
+            .iter()
+            .chain(std::iter::once(&f64::INFINITY))
+            .zip(&self.counts)
+            .map(|(bound, count)| {
+                total += count;
+                (*bound, total)
+            })
+            .collect()
+    }
+
+    // Estimated as the upper bound of the bucket the percentile falls in,
+    // capped by the largest value seen
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = ((p / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        self.cumulative()
+            .into_iter()
+            .find(|(_, total)| *total >= rank)
+            .map(|(bound, _)| bound.min(self.max_ms))
+            .unwrap_or(self.max_ms)
+    }
+
+    fn summary(&self) -> Value {
+        json!({
+            "count": self.count,
+            "mean_ms": if self.count > 0 { self.sum_ms / self.count as f64 } else { 0.0 },
+            "max_ms": self.max_ms,
+            "p50_ms": self.percentile(50.0),
+            "p90_ms": self.percentile(90.0),
+            "p99_ms": self.percentile(99.0),
+            "buckets": self
+                .cumulative()
+                .into_iter()
+                .map(|(bound, total)| json!({
+                    "le": if bound.is_finite() { json!(bound) } else { json!("+Inf") },
+                    "count": total,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct LatencyStats {
+    routes: Mutex<BTreeMap<String, Histogram>>,
+}
+
+impl LatencyStats {
+    pub fn record(&self, route: &str, ms: f64) {
+        let mut routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+        routes.entry(route.to_string()).or_default().record(ms);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, Histogram> {
+        self.routes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+// Percentiles per route: GET /api/latency
+pub struct LatencyReport(pub Arc<LatencyStats>);
+
+impl Handler for LatencyReport {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let routes: serde_json::Map<String, Value> = self
+            .0
+            .snapshot()
+            .into_iter()
+            .map(|(route, histogram)| (route, histogram.summary()))
+            .collect();
+        Ok(json_response(StatusCode::OK, &Value::Object(routes)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_land_in_the_first_bucket_that_holds_them() {
+        let mut histogram = Histogram::default();
+        for ms in [0.05, 0.1, 0.2, 3.0, 10000.0, 20000.0] {
+            histogram.record(ms);
+        }
+        let cumulative = histogram.cumulative();
+        assert_eq!(cumulative.len(), BUCKETS_MS.len() + 1);
+        assert_eq!(cumulative[0], (0.1, 2));
+        assert_eq!(cumulative[1], (0.25, 3));
+        assert_eq!(cumulative[5], (5.0, 4));
+        assert_eq!(cumulative[BUCKETS_MS.len() - 1], (10000.0, 5));
+        assert_eq!(cumulative[BUCKETS_MS.len()], (f64::INFINITY, 6));
+        assert_eq!(histogram.count(), 6);
+        assert!((histogram.sum_ms() - 30003.35).abs() < 1e-9);
+    }
+
+    #[test]
+    fn percentiles_are_bucket_bounds_capped_by_max() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(50.0), 0.0);
+        for _ in 0..9 {
+            histogram.record(0.7);
+        }
+        histogram.record(30.0);
+        assert_eq!(histogram.percentile(50.0), 1.0);
+        assert_eq!(histogram.percentile(90.0), 1.0);
+        assert_eq!(histogram.percentile(99.0), 30.0);
+        histogram.record(60000.0);
+        assert_eq!(histogram.percentile(100.0), 60000.0);
+    }
+
+    #[test]
+    fn routes_are_kept_apart() {
+        let stats = LatencyStats::default();
+        stats.record("/api/echo", 1.0);
+        stats.record("/api/echo", 2.0);
+        stats.record("/", 3.0);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot["/api/echo"].count(), 2);
+        assert_eq!(snapshot["/"].count(), 1);
+    }
+}
@@ -0,0 +1,39 @@
+// Live reload, for --dev: the page listens to GET /dev/build, which carries
+// the build ID (the hash of the JS bundle), and reloads itself when a
+// restarted server reports a different one. The browser keeps reconnecting
+// while the server is down, so a `cargo watch` rebuild is picked up on its own.
+
+use iron::Handler;
+use iron::prelude::*;
+
+use crate::sse::{self, Event};
+
+pub const BUILD_EVENTS_PATH: &str = "/dev/build";
+
+// How often the page asks again, in milliseconds
+const RETRY_MS: u64 = 1000;
+
+pub struct BuildEvents {
+    build_id: String,
+}
+
+impl BuildEvents {
+    pub fn new(build_id: &str) -> Self {
+        BuildEvents {
+            build_id: build_id.to_string(),
+        }
+    }
+}
+
+impl Handler for BuildEvents {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        Ok(sse::event_stream(
+            RETRY_MS,
+            &[Event {
+                event: Some("build".to_string()),
+                id: Some(self.build_id.clone()),
+                data: self.build_id.clone(),
+            }],
+        ))
+    }
+}
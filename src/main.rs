@@ -1,16 +1,64 @@
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+
 use iron::prelude::*;
-use iron::{AfterMiddleware, BeforeMiddleware, typemap};
-use time::precise_time_ns;
+use iron::{AroundMiddleware, Handler};
 
 use mojes::{js_object, js_type, to_js};
 
 use mojes::dom::*;
-
-#[js_type]
-#[derive(Debug)]
-struct Person {
-    name: String,
-    age: u32,
+use serde::{Deserialize, Serialize};
+
+mod access_log;
+mod api;
+mod assets;
+mod body;
+mod compression;
+mod config;
+mod csp;
+mod debug_fmt;
+mod errors;
+mod events;
+mod faults;
+mod js_graph;
+mod latency;
+mod live_reload;
+mod metrics;
+mod request_id;
+mod router;
+mod runtime;
+mod security;
+mod shutdown;
+mod sourcemap;
+mod sse;
+mod websocket;
+
+use access_log::AccessLog;
+use assets::{Assets, EntryPoints, PageContext, Startup};
+use compression::Compression;
+use config::{BundleFormat, Config, DemoGroup};
+use csp::NoncedPage;
+use debug_fmt::{DebugType, debug_type};
+use errors::CatchErrors;
+use events::{EventBus, EventsEndpoint};
+use faults::FaultInjector;
+use latency::LatencyReport;
+use live_reload::BuildEvents;
+use metrics::MetricsEndpoint;
+use request_id::RequestIds;
+use router::Router;
+use security::{Cors, SecurityHeaders};
+use shutdown::Tracked;
+
+debug_type! {
+    #[js_type]
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Person {
+        name: String,
+        age: u32,
+    }
 }
 
 #[js_object]
@@ -32,6 +80,23 @@ impl Person {
     }
 }
 
+// What the WebSocket chat demo sends and receives, as JSON.
+// `kind` is "echo" or "chat" from the page, "welcome" or "error" from the server.
+debug_type! {
+    #[js_type]
+    #[derive(Debug, Serialize, Deserialize)]
+    struct ChatMessage {
+        kind: String,
+        author: Person,
+        text: String,
+    }
+}
+
+// The #[js_type] types, for the bundle to register with the runtime's Debug formatter
+fn debug_types() -> Vec<DebugType> {
+    vec![Person::debug_type(), ChatMessage::debug_type()]
+}
+
 // Simple function with basic operations
 #[to_js]
 fn add(a: i32, b: i32) -> i32 {
@@ -52,6 +117,17 @@ fn factorial(n: i32) -> i32 {
     result
 }
 
+// Text as HTML that shows it as is, for markup built with format!(). Not transpiled:
+// the runtime has the JS version, so log_string() and the like can be given text
+// that came from elsewhere.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
 #[to_js]
 fn log_string(s: &str) {
     let mut elt = document.getElementById("debugs").unwrap();
@@ -313,6 +389,11 @@ fn make_get_request(url: &str) {
 
         if xhr.readyState == 4 {
             // xhr_ready_state::DONE {
+            console.log(&format!(
+                "Request ID: {:?}, Server-Timing: {:?}",
+                xhr.getResponseHeader("x-request-id"),
+                xhr.getResponseHeader("server-timing")
+            ));
             if xhr.status == 200 {
                 console.log(&format!("Success: {}", xhr.responseText));
             } else {
@@ -327,32 +408,84 @@ fn make_get_request(url: &str) {
     xhr.send();
 }
 
-/* FIXME LATER
+// Debug output of a few values. The server runs this natively for GET /api/debug-parity,
+// and debug_parity_check() compares that with what the transpiled copy gives.
+#[to_js]
+fn debug_parity_samples() -> Vec<String> {
+    let person = Person::new("Ada \"Lovelace\"\n".to_string(), 36);
+    let message = ChatMessage {
+        kind: "chat".to_string(),
+        author: Person::new("Grace".to_string(), 45),
+        text: "tab\there".to_string(),
+    };
+    let result: Result<u32, String> = Err("nope".to_string());
+    vec![
+        format!("{:?}", person),
+        format!("{:#?}", message),
+        format!("{:?}", result),
+        format!("{:?}", vec![0.5, 1e16, 1e-5, 123456.789]),
+    ]
+}
+
+// Fetch the native Debug output from `url` and report where the transpiled one differs
+#[to_js]
+fn debug_parity_check(url: &str) {
+    let xhr_orig = Arc::new(Mutex::new(XMLHttpRequest::new()));
+    let mut xhr = xhr_orig.lock().unwrap();
+    xhr.open("GET", url);
+
+    let xhr1 = xhr_orig.clone();
+    xhr.addEventListener("load", move || {
+        let xhr = xhr1.lock().unwrap();
+        let native: Vec<String> = JSON.parse(&xhr.responseText);
+        let transpiled = debug_parity_samples();
+        let mut matching = 0;
+        for (i, expected) in native.iter().enumerate() {
+            if &transpiled[i] == expected {
+                matching += 1;
+            } else {
+                console.error(&format!(
+                    "Debug parity sample {}: native\n{}\ntranspiled\n{}",
+                    i, expected, transpiled[i]
+                ));
+            }
+        }
+        log_string(&format!(
+            "Debug parity: {} of {} samples match native Rust",
+            matching,
+            native.len()
+        ));
+    });
+
+    xhr.send();
+}
+
+// The readyState values are compared as numbers, as in make_get_request above:
+// 1 = OPENED, 2 = HEADERS_RECEIVED, 3 = LOADING, 4 = DONE
 
 #[to_js]
 fn make_post_request(url: &str, data: &str) {
-    let mut xhr = XMLHttpRequest::new();
+    let xhr_orig = Arc::new(Mutex::new(XMLHttpRequest::new()));
+    let mut xhr = xhr_orig.lock().unwrap();
 
     // Handle different response states
-    xhr.addEventListener("readystatechange", || match xhr.readyState {
-        xhr_ready_state::OPENED => {
+    let xhr1 = xhr_orig.clone();
+    xhr.addEventListener("readystatechange", move || {
+        let xhr = xhr1.lock().unwrap();
+        if xhr.readyState == 1 {
             console.log("Request opened");
-        }
-        xhr_ready_state::HEADERS_RECEIVED => {
+        } else if xhr.readyState == 2 {
             console.log("Headers received");
             console.log(&format!(
                 "Content-Type: {:?}",
                 xhr.getResponseHeader("content-type")
             ));
-        }
-        xhr_ready_state::LOADING => {
+        } else if xhr.readyState == 3 {
             console.log("Loading response...");
-        }
-        xhr_ready_state::DONE => {
+        } else if xhr.readyState == 4 {
             console.log("Request completed");
             handle_response(&xhr);
         }
-        _ => {}
     });
 
     // Set up progress tracking
@@ -377,6 +510,10 @@ fn make_post_request(url: &str, data: &str) {
 
 #[to_js]
 fn handle_response(xhr: &XMLHttpRequest) {
+    console.log(&format!(
+        "Request ID: {:?}",
+        xhr.getResponseHeader("x-request-id")
+    ));
     if xhr.status >= 200 && xhr.status < 300 {
         // Success
         console.log(&format!("Response: {}", xhr.responseText));
@@ -438,40 +575,64 @@ fn upload_with_progress(url: &str, file_data: &str) {
     // Set timeout
     xhr.timeout = 30000; // 30 seconds
 
+    // A two-part form: the file, then a plain field
+    let boundary = "mojes-sample-upload-boundary";
+    let delimiter = format!("--{}", boundary);
+    let body = format!(
+        "{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"data.txt\"\r\nContent-Type: text/plain\r\n\r\n{}\r\n{}\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nupload demo\r\n{}--\r\n",
+        delimiter, file_data, delimiter, delimiter
+    );
+
     xhr.open("POST", url);
-    xhr.setRequestHeader("Content-Type", "multipart/form-data");
-    xhr.send_with_body(Some(file_data));
+    xhr.setRequestHeader(
+        "Content-Type",
+        &format!("multipart/form-data; boundary={}", boundary),
+    );
+    xhr.send_with_body(Some(&body));
 }
 
 #[to_js]
-fn abort_request_example() {
-    let mut xhr = XMLHttpRequest::new();
+fn abort_request_example(url: &str) {
+    let xhr_orig = Arc::new(Mutex::new(XMLHttpRequest::new()));
+    let mut xhr = xhr_orig.lock().unwrap();
 
     xhr.addEventListener("abort", || {
         console.log("Request was aborted");
     });
 
-    xhr.open("GET", "https://api.example.com/large-file");
+    xhr.addEventListener("load", || {
+        console.log("Download finished before it could be aborted");
+    });
+
+    xhr.open("GET", url);
     xhr.send();
 
-    // Abort after 5 seconds (in a real scenario, this might be triggered by user action)
+    // Abort after a second, while the slow download is still in flight
+    let xhr1 = xhr_orig.clone();
     setTimeout(
         move || {
-            xhr.abort();
+            xhr1.lock().unwrap().abort();
         },
-        5000,
+        1000,
     );
 }
 
 #[to_js]
-fn fetch_with_credentials(url: &str) {
-    let mut xhr = XMLHttpRequest::new();
+fn fetch_with_credentials(url: &str, token: &str) {
+    let xhr_orig = Arc::new(Mutex::new(XMLHttpRequest::new()));
+    let mut xhr = xhr_orig.lock().unwrap();
 
     // Enable credentials for cross-origin requests
     xhr.withCredentials = true;
 
-    xhr.addEventListener("readystatechange", || {
-        if xhr.readyState == xhr_ready_state::DONE {
+    let xhr1 = xhr_orig.clone();
+    xhr.addEventListener("readystatechange", move || {
+        let xhr = xhr1.lock().unwrap();
+        if xhr.readyState == 4 {
+            console.log(&format!(
+                "Request ID: {:?}",
+                xhr.getResponseHeader("x-request-id")
+            ));
             if xhr.status == 200 {
                 console.log("Authenticated request successful");
                 console.log(&xhr.responseText);
@@ -484,20 +645,27 @@ fn fetch_with_credentials(url: &str) {
     });
 
     xhr.open("GET", url);
-    xhr.setRequestHeader("Authorization", "Bearer your-token-here");
+    // An empty token sends a bare "Bearer", which the server treats as no credentials
+    xhr.setRequestHeader("Authorization", &format!("Bearer {}", token));
     xhr.send();
 }
 
 #[to_js]
 fn handle_different_response_types(url: &str, response_type: &str) {
-    let mut xhr = XMLHttpRequest::new();
+    let xhr_orig = Arc::new(Mutex::new(XMLHttpRequest::new()));
+    let mut xhr = xhr_orig.lock().unwrap();
 
     // Set response type before sending
     xhr.responseType = response_type.to_string();
 
-    xhr.addEventListener("load", || {
+    let xhr1 = xhr_orig.clone();
+    xhr.addEventListener("load", move || {
+        let xhr = xhr1.lock().unwrap();
+        console.log(&format!(
+            "Request ID: {:?}",
+            xhr.getResponseHeader("x-request-id")
+        ));
         match xhr.responseType.as_str() {
-            // "text" | "" => {
             "text" => {
                 console.log(&format!("Text response: {}", xhr.responseText));
             }
@@ -528,6 +696,8 @@ fn handle_different_response_types(url: &str, response_type: &str) {
     xhr.send();
 }
 
+/* FIXME LATER
+
 // Example of a utility function for making AJAX calls
 #[to_js]
 fn ajax_get(url: &str, success_callback: fn(&str), error_callback: fn(u16, &str)) {
@@ -570,42 +740,404 @@ fn ajax_post(url: &str, data: &str, success_callback: fn(&str), error_callback:
 
 END FIXME LATER */
 
-struct ResponseTime;
+// Server push: subscribe to /events and show what arrives in #debugs.
+// The browser reconnects after every batch of events, so open and error
+// only go to the console. The subscription ends after a minute.
+#[to_js]
+fn eventSourceExample() {
+    let source_orig = Arc::new(Mutex::new(EventSource::new("/events")));
+    let mut source = source_orig.lock().unwrap();
+
+    source.addEventListener("open", |_| {
+        console.log("EventSource connected");
+    });
+    source.addEventListener("error", |_| {
+        console.log("EventSource disconnected, the browser will reconnect");
+    });
+    source.addEventListener("message", |e| {
+        log_string(&format!("message: {}", escape_html(&e.data)));
+    });
+    source.addEventListener("tick", |e| {
+        log_string(&format!("tick: {}", escape_html(&e.data)));
+    });
+    source.addEventListener("log", |e| {
+        log_string(&format!("log: {}", escape_html(&e.data)));
+    });
+
+    let source1 = source_orig.clone();
+    setTimeout(
+        move || {
+            source1.lock().unwrap().close();
+            log_string("EventSource closed");
+        },
+        60000,
+    );
+}
+
+// What other clients send is text, not markup
+#[to_js]
+fn render_chat_message(message: &ChatMessage) {
+    let mut chat_log = document.getElementById("chatLog").unwrap();
+    chat_log.insertAdjacentHTML(
+        "beforeend",
+        &format!(
+            "<p>[{}] {} ({}): {}</p>",
+            escape_html(&message.kind),
+            escape_html(&message.author.name),
+            message.author.age,
+            escape_html(&message.text)
+        ),
+    );
+}
+
+// Build a ChatMessage from the chat form and send it
+#[to_js]
+fn send_chat_message(socket: &WebSocket, kind: &str) {
+    let name = document.getElementById("chatName").unwrap().value.clone();
+    let age: u32 = document
+        .getElementById("chatAge")
+        .unwrap()
+        .value
+        .parse()
+        .unwrap();
+    let text = document.getElementById("chatText").unwrap().value.clone();
+    let message = ChatMessage {
+        kind: kind.to_string(),
+        author: Person::new(name, age),
+        text,
+    };
+    socket.send(&JSON.stringify(&message));
+    console.log(&format!("Sent {}: {}", kind, message.author.greet()));
+}
+
+// WebSocket chat: connect to the server's room on `port`, then let the
+// Echo and Send buttons use the connection. Other tabs see "chat" messages too.
+#[to_js]
+fn websocketExample(port: &str) {
+    let url = format!("ws://{}:{}/", location.hostname, port);
+    let socket_orig = Arc::new(Mutex::new(WebSocket::new(&url)));
+    let mut socket = socket_orig.lock().unwrap();
+
+    socket.addEventListener("open", |_| {
+        log_string("WebSocket connected");
+    });
+    socket.addEventListener("close", |_| {
+        log_string("WebSocket closed");
+    });
+    socket.addEventListener("error", |_| {
+        console.error("WebSocket error");
+    });
+    socket.addEventListener("message", |e| {
+        let message: ChatMessage = JSON.parse(&e.data);
+        render_chat_message(&message);
+    });
+
+    let socket1 = socket_orig.clone();
+    let echo_button = document.getElementById("chatEcho").unwrap();
+    echo_button.addEventListener("click", move |_| {
+        send_chat_message(&socket1.lock().unwrap(), "echo");
+    });
+    let socket2 = socket_orig.clone();
+    let send_button = document.getElementById("chatSend").unwrap();
+    send_button.addEventListener("click", move |_| {
+        send_chat_message(&socket2.lock().unwrap(), "chat");
+    });
+}
 
-impl typemap::Key for ResponseTime {
-    type Value = u64;
+// Dev mode: reload the page once the server comes back with another build.
+// The URL is live_reload::BUILD_EVENTS_PATH.
+#[to_js]
+fn live_reload(build_id: &str) {
+    let events = EventSource::new("/dev/build");
+    let current = build_id.to_string();
+    events.addEventListener("build", move |e| {
+        if e.data != current {
+            console.log(&format!(
+                "Build changed from {} to {}, reloading",
+                current, e.data
+            ));
+            location.reload();
+        }
+    });
+    console.log(&format!("Live reload on, build {}", build_id));
 }
 
-impl BeforeMiddleware for ResponseTime {
-    fn before(&self, req: &mut Request) -> IronResult<()> {
-        req.extensions.insert::<ResponseTime>(precise_time_ns());
-        Ok(())
+// What the buttons of the demo page do: `demo` is their data-demo attribute,
+// `arg` and `arg2` their data-arg and data-arg2 attributes
+#[to_js]
+fn run_demo(demo: &str, arg: Option<String>, arg2: Option<String>) {
+    match demo {
+        "testFunc" => testFunc(),
+        "add" => console.log(&format!("Simple calculation: {}", add(5, 3))),
+        "factorial" => console.log(&format!("Factorial of 5: {}", factorial(5))),
+        "get" => make_get_request(&arg.unwrap()),
+        "post" => make_post_request(&arg.unwrap(), &arg2.unwrap()),
+        "upload" => upload_with_progress(&arg.unwrap(), &"x".repeat(2 * 1024 * 1024)),
+        "abort" => abort_request_example(&arg.unwrap()),
+        "auth" => fetch_with_credentials(&arg.unwrap(), &arg2.unwrap()),
+        // `arg` is the port of the second origin, on the host the page came from
+        "cross_origin_auth" => fetch_with_credentials(
+            &format!(
+                "http://{}:{}/api/protected",
+                location.hostname,
+                arg.unwrap()
+            ),
+            &arg2.unwrap(),
+        ),
+        "response_type" => handle_different_response_types(&arg.unwrap(), &arg2.unwrap()),
+        "domExample" => domExample(),
+        "styleExample" => styleExample(),
+        "eventExample" => eventExample(),
+        "timerExample" => timerExample(),
+        "animationExample" => animationExample(),
+        "navigationExample" => navigationExample(),
+        "formExample" => formExample(),
+        "storageExample" => storageExample(),
+        "eventSourceExample" => eventSourceExample(),
+        "websocketExample" => websocketExample(&arg.unwrap()),
+        "log" => console.log(&arg.unwrap()),
+        "debug_parity" => debug_parity_check(&arg.unwrap()),
+        _ => console.error(&format!("Unknown demo: {}", demo)),
     }
 }
 
-impl AfterMiddleware for ResponseTime {
-    fn after(&self, req: &mut Request, res: Response) -> IronResult<Response> {
-        let delta = precise_time_ns() - *req.extensions.get::<ResponseTime>().unwrap();
-        println!("Request took: {} ms", (delta as f64) / 1000000.0);
-        Ok(res)
+// The page runs under a Content-Security-Policy that forbids inline
+// onclick handlers, so the buttons get their listeners from here instead
+#[to_js]
+fn wire_demo_buttons() {
+    let buttons = document.querySelectorAll("button[data-demo]");
+    let mut count = 0;
+    for button in buttons.iter() {
+        let demo = button.getAttribute("data-demo").unwrap();
+        let arg = button.getAttribute("data-arg");
+        let arg2 = button.getAttribute("data-arg2");
+        button.addEventListener("click", move |_| {
+            run_demo(&demo, arg.clone(), arg2.clone());
+        });
+        count += 1;
     }
+    console.log(&format!("Wired {} demo buttons", count));
 }
 
-fn hello_world(_: &mut Request) -> IronResult<Response> {
-    use iron::StatusCode;
-    use iron::mime;
-    let content_type = "text/html".parse::<mime::Mime>().unwrap();
+// Start the page's demos once it has loaded: wire up the buttons and,
+// given the build ID in dev mode, keep watching for a new build
+#[to_js]
+fn start_page(build_id: Option<String>) {
+    wire_demo_buttons();
+    if let Some(build_id) = build_id {
+        live_reload(&build_id);
+    }
+}
 
-    // Generate JavaScript with proper DOM API shims (no need for shimming since we use exact JS names)
-    let js_code = format!(
-        r#"
-// Transpiled Rust functions with native JavaScript DOM API calls
-{}
-"#,
-        JS.join("\n")
-    );
+// Where the page enters the transpiled code: start_page() once it has loaded, then
+// run_demo() for the buttons. What a button needs is what the arm of run_demo()
+// matching its data-demo calls, for the buttons of the sections shown.
+fn entry_points(config: &Config) -> EntryPoints {
+    let dispatch = js_graph::dispatch_table(sourcemap::RUST_SOURCE, "run_demo");
+    let mut roots = BTreeSet::from(["start_page"]);
+    for demo in demo_names(&demo_sections(config)) {
+        match dispatch.get(demo) {
+            Some(uses) => roots.extend(uses.iter().copied()),
+            None => eprintln!("⚠️  No arm of run_demo() handles the {:?} button", demo),
+        }
+    }
+    EntryPoints {
+        roots: roots.into_iter().collect(),
+        dispatchers: &["run_demo"],
+    }
+}
+
+// The data-demo attributes of the buttons in `html`
+fn demo_names(html: &str) -> BTreeSet<&str> {
+    html.split("data-demo=\"")
+        .skip(1)
+        .filter_map(|rest| rest.split_once('"'))
+        .map(|(name, _)| name)
+        .collect()
+}
+
+// Stand for config.second_origin_port() and config.websocket_port() in DEMO_SECTIONS
+const SECOND_ORIGIN_PORT: &str = "SECOND_ORIGIN_PORT";
+const WEBSOCKET_PORT: &str = "WEBSOCKET_PORT";
+
+// Sections of the demo page, each shown only if its demo group is enabled
+const DEMO_SECTIONS: &[(DemoGroup, &str)] = &[
+    (
+        DemoGroup::Basic,
+        r#"        <div class="demo-section">
+            <h3>Basic Functions</h3>
+            <div id='test'>Test Element</div>
+            <button data-demo="testFunc">Test Basic Function</button>
+            <button data-demo="add">Test Add Function</button>
+            <button data-demo="factorial">Test Factorial</button>
+            <button data-demo="get" data-arg="/api/echo?demo=xhr">Test XHR (see console)</button>
+            <button data-demo="get" data-arg="/api/missing">Test XHR error (see console)</button>
+            <button data-demo="get" data-arg="/api/panic">Test XHR server panic (see console)</button>
+            <a href="/panic">Server panic error page</a>
+        </div>"#,
+    ),
+    (
+        DemoGroup::Xhr,
+        r#"        <div class="demo-section">
+            <h3>XHR Suite</h3>
+            <button data-demo="post" data-arg="/api/echo" data-arg2='{"name": "John Doe", "age": 30}'>POST JSON</button>
+            <button data-demo="upload" data-arg="/api/upload">Upload 2 MB</button>
+            <button data-demo="abort" data-arg="/api/large-file">Abort Slow Download</button>
+            <br>
+            <button data-demo="auth" data-arg="/api/protected" data-arg2="demo-token">Auth: Valid Token</button>
+            <button data-demo="auth" data-arg="/api/protected" data-arg2="wrong-token">Auth: Wrong Token (403)</button>
+            <button data-demo="auth" data-arg="/api/protected" data-arg2="">Auth: No Token (401)</button>
+            <button data-demo="cross_origin_auth" data-arg="SECOND_ORIGIN_PORT" data-arg2="demo-token">Cross-Origin Auth: Valid Token</button>
+            <button data-demo="cross_origin_auth" data-arg="SECOND_ORIGIN_PORT" data-arg2="">Cross-Origin Auth: No Token (401)</button>
+            <br>
+            <button data-demo="response_type" data-arg="/api/resource?type=text" data-arg2="text">Text</button>
+            <button data-demo="response_type" data-arg="/api/resource?type=json" data-arg2="json">JSON</button>
+            <button data-demo="response_type" data-arg="/api/resource?type=blob" data-arg2="blob">Blob</button>
+            <button data-demo="response_type" data-arg="/api/resource?type=arraybuffer" data-arg2="arraybuffer">ArrayBuffer</button>
+            <button data-demo="response_type" data-arg="/api/resource?type=document" data-arg2="document">Document</button>
+            <br>
+            <button data-demo="get" data-arg="/api/echo?fault_delay=2000">Fault: 2 s Latency</button>
+            <button data-demo="post" data-arg="/api/echo?fault_status=418" data-arg2="{}">Fault: 418</button>
+            <button data-demo="post" data-arg="/api/echo?fault_status=503" data-arg2="{}">Fault: 503</button>
+            <button data-demo="get" data-arg="/api/echo?fault_truncate=20">Fault: Truncated Body</button>
+            <button data-demo="get" data-arg="/api/resource?fault_throttle=5">Fault: Throttled</button>
+            <button data-demo="get" data-arg="/api/echo?fault_drop=1">Fault: Dropped Connection</button>
+        </div>"#,
+    ),
+    (
+        DemoGroup::Dom,
+        r#"        <div class="demo-section">
+            <h3>DOM Manipulation</h3>
+            <p>Paragraph 1</p>
+            <p>Paragraph 2</p>
+            <p>Paragraph 3</p>
+            <button data-demo="domExample">Test DOM Operations</button>
+            <button id="myButton" data-demo="log" data-arg="Button clicked directly!">Target Button</button>
+        </div>"#,
+    ),
+    (
+        DemoGroup::Style,
+        r#"        <div class="demo-section">
+            <h3>CSS Styling</h3>
+            <div id="styledElement">Styled Element</div>
+            <button data-demo="styleExample">Test Style Operations</button>
+        </div>"#,
+    ),
+    (
+        DemoGroup::Events,
+        r#"        <div class="demo-section">
+            <h3>Event Handling</h3>
+            <button class="clickable">Clickable 1</button>
+            <button class="clickable">Clickable 2</button>
+            <button class="clickable">Clickable 3</button>
+            <br>
+            <button data-demo="eventExample">Setup Event Listeners</button>
+        </div>"#,
+    ),
+    (
+        DemoGroup::Timers,
+        r#"        <div class="demo-section">
+            <h3>Timers & Animation</h3>
+            <div id="animatedElement"></div>
+            <button data-demo="timerExample">Test Timers</button>
+            <button data-demo="animationExample">Test Animation</button>
+        </div>"#,
+    ),
+    (
+        DemoGroup::Navigation,
+        r#"        <div class="demo-section">
+            <h3>Navigation & Browser Info</h3>
+            <button data-demo="navigationExample">Test Navigation</button>
+            <button data-demo="log" data-arg="Current page info logged to console">Log Page Info</button>
+        </div>"#,
+    ),
+    (
+        DemoGroup::Forms,
+        r#"        <div class="demo-section">
+            <h3>Form Handling</h3>
+            <form>
+                <label>Name: <input type="text" name="name" value="John Doe"></label><br>
+                <label>Email: <input type="email" name="email" value="john@example.com"></label><br>
+                <label>Age: <input type="number" name="age" value="30"></label><br>
+            </form>
+            <button data-demo="formExample">Read Form Values</button>
+        </div>"#,
+    ),
+    (
+        DemoGroup::Storage,
+        r#"        <div class="demo-section">
+            <h3>Storage Operations</h3>
+            <button data-demo="storageExample">Test Storage</button>
+        </div>"#,
+    ),
+    (
+        DemoGroup::Console,
+        r#"        <div class="demo-section">
+            <h3>Console Output</h3>
+            <p>Open the browser's developer tools console (F12) to see the output from the Rust functions.</p>
+            <button data-demo="log" data-arg="Manual console test from HTML">Manual Console Test</button>
+            <button data-demo="debug_parity" data-arg="/api/debug-parity">Debug Parity Check</button>
+        </div>"#,
+    ),
+    (
+        DemoGroup::Push,
+        r#"        <div class="demo-section">
+            <h3>Server Push</h3>
+            <button data-demo="eventSourceExample">Subscribe to /events</button>
+            <button data-demo="post" data-arg="/events" data-arg2="Hello from the page">Publish a Message</button>
+            <h4>WebSocket Chat</h4>
+            <button data-demo="websocketExample" data-arg="WEBSOCKET_PORT">Connect</button>
+            <br>
+            <label>Name: <input type="text" id="chatName" value="John Doe"></label><br>
+            <label>Age: <input type="number" id="chatAge" value="30"></label><br>
+            <label>Message: <input type="text" id="chatText" value="Hello, room!"></label><br>
+            <button id="chatEcho">Echo</button>
+            <button id="chatSend">Send to Room</button>
+            <div id="chatLog"></div>
+        </div>"#,
+    ),
+];
+
+// The sections of the enabled demo groups
+fn demo_sections(config: &Config) -> String {
+    DEMO_SECTIONS
+        .iter()
+        .filter(|(group, _)| config.demos.contains(group))
+        .map(|(_, html)| *html)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+        .replace(SECOND_ORIGIN_PORT, &config.second_origin_port().to_string())
+        .replace(WEBSOCKET_PORT, &config.websocket_port().to_string())
+}
 
-    let data = format!(
+// The demo page; rendered once at startup, see assets::Assets::build(),
+// then served with a fresh script nonce each time by csp::NoncedPage.
+// The runtime prelude and the transpiled functions are served separately,
+// from URLs which change with their content, and started with start_page().
+fn demo_page(page: &PageContext, config: &Config) -> String {
+    let sections = demo_sections(config);
+    // A module bundle is started by its bootstrap, a script one by the page itself
+    let (scripts, startup) = match &page.bootstrap_url {
+        Some(bootstrap_url) => (
+            format!(
+                r#"    <script type="module" nonce="{nonce}" src="{bootstrap_url}"></script>"#,
+                nonce = csp::NONCE_PLACEHOLDER
+            ),
+            "",
+        ),
+        None => (
+            format!(
+                r#"    <script nonce="{nonce}" src="{}"></script>
+    <script nonce="{nonce}" src="{}"></script>"#,
+                page.runtime_url,
+                page.app_js_url,
+                nonce = csp::NONCE_PLACEHOLDER
+            ),
+            page.startup.as_str(),
+        ),
+    };
+
+    format!(
         r#"<!DOCTYPE html>
 <html>
 <head>
@@ -697,30 +1229,7 @@ fn hello_world(_: &mut Request) -> IronResult<Response> {
             margin: 10px 0;
         }}
     </style>
-    <script>
-class Mutex {{
-  constructor(inner) {{
-    this.inner = inner;
-  }}
-
-  lock() {{
-    return this.inner;
-  }}
-}}
-
-class Arc {{
-  constructor(inner) {{
-    this.inner = inner;
-  }}
-
-  lock() {{
-    return this.inner.lock();
-  }}
-}}
-
-{}
-
-</script>
+{scripts}
 </head>
 <body>
     <div class="container">
@@ -729,90 +1238,17 @@ class Arc {{
         <div id="debugs" style="width:100%; height: 100px; border:solid 1px; overflow: auto;">
         </div>
         
-        <div class="demo-section">
-            <h3>Basic Functions</h3>
-            <div id='test'>Test Element</div>
-            <button onclick="testFunc()">Test Basic Function</button>
-            <button onclick="console.log('Simple calculation: ' + add(5, 3))">Test Add Function</button>
-            <button onclick="console.log('Factorial of 5: ' + factorial(5))">Test Factorial</button>
-            <button onclick="make_get_request('http://localhost:3000/')">Test XHR (see console)</button>
-        </div>
-
-        <div class="demo-section">
-            <h3>DOM Manipulation</h3>
-            <p>Paragraph 1</p>
-            <p>Paragraph 2</p>
-            <p>Paragraph 3</p>
-            <button onclick="domExample()">Test DOM Operations</button>
-            <button id="myButton" onclick="console.log('Button clicked directly!')">Target Button</button>
-        </div>
-
-        <div class="demo-section">
-            <h3>CSS Styling</h3>
-            <div id="styledElement">Styled Element</div>
-            <button onclick="styleExample()">Test Style Operations</button>
-        </div>
-
-        <div class="demo-section">
-            <h3>Event Handling</h3>
-            <button class="clickable">Clickable 1</button>
-            <button class="clickable">Clickable 2</button>
-            <button class="clickable">Clickable 3</button>
-            <br>
-            <button onclick="eventExample()">Setup Event Listeners</button>
-        </div>
-
-        <div class="demo-section">
-            <h3>Timers & Animation</h3>
-            <div id="animatedElement"></div>
-            <button onclick="timerExample()">Test Timers</button>
-            <button onclick="animationExample()">Test Animation</button>
-        </div>
-
-        <div class="demo-section">
-            <h3>Navigation & Browser Info</h3>
-            <button onclick="navigationExample()">Test Navigation</button>
-            <button onclick="console.log('Current page info logged to console')">Log Page Info</button>
-        </div>
-
-        <div class="demo-section">
-            <h3>Form Handling</h3>
-            <form>
-                <label>Name: <input type="text" name="name" value="John Doe"></label><br>
-                <label>Email: <input type="email" name="email" value="john@example.com"></label><br>
-                <label>Age: <input type="number" name="age" value="30"></label><br>
-            </form>
-            <button onclick="formExample()">Read Form Values</button>
-        </div>
-
-        <div class="demo-section">
-            <h3>Storage Operations</h3>
-            <button onclick="storageExample()">Test Storage</button>
-        </div>
-
-        <div class="demo-section">
-            <h3>Console Output</h3>
-            <p>Open the browser's developer tools console (F12) to see the output from the Rust functions.</p>
-            <button onclick="console.log('Manual console test from HTML')">Manual Console Test</button>
-        </div>
+{}
     </div>
 
-    <script>
-
-/* Bring your own JS debug convert function */
-function debug_repr(obj) {{
-    if (obj === null) return "null";
-    if (obj === undefined) return "undefined";
-    if (typeof obj === "string") return `"${{obj}}"`;
-    if (typeof obj === "object") return JSON.stringify(obj);
-    return String(obj);
-}}
+    <script nonce="{nonce}">
         // Additional JavaScript to enhance the demo
         console.log('=== Rust-to-JS Transpiler Demo Started ===');
         
         // Add some interactivity to demonstrate the transpiled functions work correctly
         document.addEventListener('DOMContentLoaded', function() {{
             console.log('DOM loaded, all Rust-transpiled functions ready!');
+            {startup}
             
             // Test that our transpiled functions exist
             if (typeof testFunc === 'function') {{
@@ -836,19 +1272,355 @@ function debug_repr(obj) {{
     </script>
 </body>
 </html>"#,
-        js_code
-    );
+        sections,
+        scripts = scripts,
+        startup = startup,
+        nonce = csp::NONCE_PLACEHOLDER,
+    )
+}
 
-    Ok(Response::with((content_type, StatusCode::OK, data)))
+// A handler for one more listener, sharing the state of the others
+fn shared<H: Handler>(handler: &Arc<H>) -> impl Handler {
+    let handler = handler.clone();
+    move |req: &mut Request| handler.handle(req)
+}
+
+// How long in-flight requests get to finish after SIGINT/SIGTERM
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+// Why a listener may fail to start: another instance also takes the two ports
+// after its own, so `--port 3001` clashes with a default instance on 3000
+fn port_clash_hint(config: &Config) -> String {
+    format!(
+        "this server takes ports {} (page), {} (second origin) and {} (WebSocket chat); \
+         another instance takes the port it is given and, unless told otherwise, the two after it",
+        config.port,
+        config.second_origin_port(),
+        config.websocket_port()
+    )
+}
+
+fn startup_summary(config: &Config, routes: &str, assets: &Assets) -> String {
+    format!(
+        "📦 {} transpiled items loaded from JS, {} bundle {} bytes ({}), page {} bytes ({})\n\
+         🧰 Runtime prelude v{}, {} bytes ({})\n\
+         🌳 Tree shaking: {}\n\
+         🧩 Demo groups: {:?}\n\
+         🔁 Live reload: {}\n\
+         🗺️  Routes:\n{}",
+        JS.len(),
+        match config.bundle_format {
+            BundleFormat::Script => "script",
+            BundleFormat::Module => "ES module",
+        },
+        assets.app_js.size(),
+        assets.app_js.size_report(),
+        assets.page.size(),
+        assets.page.size_report(),
+        runtime::VERSION,
+        assets.runtime.size(),
+        assets.runtime.size_report(),
+        if assets.bundle_report.tree_shaken {
+            format!(
+                "kept {} items, dropped {}: {}",
+                assets.bundle_report.kept.len(),
+                assets.bundle_report.dropped.len(),
+                if assets.bundle_report.dropped.is_empty() {
+                    "-".to_string()
+                } else {
+                    assets.bundle_report.dropped.join(", ")
+                }
+            )
+        } else {
+            "off (--full-bundle)".to_string()
+        },
+        config.demos,
+        if config.dev {
+            format!("on, build {}", assets.app_js.hash())
+        } else {
+            "off (use --dev)".to_string()
+        },
+        routes
+    )
 }
 
 fn main() {
-    let mut chain = Chain::new(hello_world);
-    chain.link_before(ResponseTime);
-    chain.link_after(ResponseTime);
-    println!("🚀 Rust-to-JS Transpiler Server starting...");
-    println!("📊 Server running on http://localhost:3000");
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("❌ {}", e);
+        std::process::exit(2);
+    });
+    let fault_rules = config.fault_rules().unwrap_or_else(|e| {
+        eprintln!("❌ {}", e);
+        std::process::exit(2);
+    });
+
+    let event_bus = Arc::new(EventBus::default());
+    events::start_ticker(event_bus.clone());
+
+    let access_log = AccessLog::new(
+        config.log_format,
+        config.access_log.as_deref(),
+        event_bus.clone(),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("❌ Cannot open access log: {}", e);
+        std::process::exit(2);
+    });
+
+    let security_headers = SecurityHeaders::new(&config.security_headers).unwrap_or_else(|e| {
+        eprintln!("❌ {}", e);
+        std::process::exit(2);
+    });
+
+    let page_entry_points = (!config.full_bundle).then(|| entry_points(&config));
+    let assets = Assets::build(
+        config.bundle_format,
+        page_entry_points.as_ref(),
+        |build_id| Startup {
+            function: "start_page",
+            args: if config.dev {
+                format!("\"{}\"", build_id)
+            } else {
+                "null".to_string()
+            },
+        },
+        |page| demo_page(page, &config),
+    );
+
+    let mut router = Router::new();
+    router.get(
+        "/",
+        NoncedPage::new(&assets.page)
+            .allow_connect(&format!("http://*:{}", config.second_origin_port()))
+            .allow_connect(&format!("ws://*:{}", config.websocket_port())),
+    );
+    router.get(assets::RUNTIME_JS_PATH, assets.runtime.clone());
+    router.get(assets::APP_JS_PATH, assets.app_js.clone());
+    router.get(assets::APP_JS_MAP_PATH, assets.app_js_map.clone());
+    if let Some(bootstrap) = &assets.bootstrap {
+        router.get(assets::BOOTSTRAP_JS_PATH, bootstrap.clone());
+    }
+    router.get(assets::BUNDLE_REPORT_PATH, assets.bundle_report.clone());
+    router.get("/api/echo", api::echo);
+    router.post("/api/echo", api::echo);
+    router.post("/api/upload", api::upload);
+    router.get("/api/large-file", api::large_file);
+    router.get("/api/protected", api::protected);
+    router.get("/api/resource", api::resource);
+    router.get(debug_fmt::PARITY_PATH, debug_fmt::parity);
+    router.get("/panic", errors::panic_demo);
+    router.get("/api/panic", errors::panic_demo);
+    if config.dev {
+        router.get(
+            live_reload::BUILD_EVENTS_PATH,
+            BuildEvents::new(assets.app_js.hash()),
+        );
+    }
+    router.get(
+        events::EVENTS_PATH,
+        EventsEndpoint::new(event_bus.clone(), config.cors_origins()),
+    );
+    router.post(
+        events::EVENTS_PATH,
+        EventsEndpoint::new(event_bus, config.cors_origins()),
+    );
+    router.get("/api/latency", LatencyReport(access_log.latency_stats()));
+    router.get(
+        "/metrics",
+        MetricsEndpoint {
+            counters: access_log.request_counters(),
+            latency: access_log.latency_stats(),
+            transpiled_items: JS.len(),
+            js_bundle_bytes: assets.app_js.size(),
+        },
+    );
+
+    // Query parameters inject faults even without any rules
+    let faults = FaultInjector::new(fault_rules);
+
+    let router_summary = router
+        .routes()
+        .map(|(method, path)| format!("     {:<6} {}", method.as_str(), path))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut chain = Chain::new(router);
+    chain.link_around(CatchErrors);
+    chain.link_around(Cors::new(config.cors_origins(), &config.cors));
+    chain.link_before(RequestIds);
+    chain.link_before(access_log.clone());
+    chain.link_before(faults.clone());
+    chain.link_after(Compression);
+    chain.link_after(faults);
+    chain.link_after(security_headers);
+    chain.link_after(RequestIds);
+    chain.link_after(access_log);
+
+    // Handler errors above pass through the after middleware like any response;
+    // this catches what the middleware itself fails with
+    let chain = CatchErrors.around(Box::new(chain));
+
+    let summary = startup_summary(&config, &router_summary, &assets);
+    let handler = Arc::new(Tracked::new(chain));
+    let shutdown = handler.shutdown();
+
+    let mut iron = Iron::new(shared(&handler));
+    if let Some(threads) = config.threads {
+        iron.threads = threads.get();
+    }
+    let listen_addr = config.listen_addr();
+    // Dropping the listener would block until the server stops, so hold on to it until exit
+    let _listening = match iron.http(&listen_addr) {
+        Ok(listening) => listening,
+        Err(e) => {
+            eprintln!(
+                "❌ Cannot listen on {}: {}\n   {}",
+                listen_addr,
+                e,
+                port_clash_hint(&config)
+            );
+            std::process::exit(1);
+        }
+    };
+
+    // The same server on another port, which the browser sees as another origin
+    let second_addr = config.second_origin_listen_addr();
+    let mut second_iron = Iron::new(shared(&handler));
+    if let Some(threads) = config.threads {
+        second_iron.threads = threads.get();
+    }
+    let second_listening = match second_iron.http(&second_addr) {
+        Ok(listening) => Some(listening),
+        Err(e) => {
+            eprintln!(
+                "⚠️  Cannot listen on {}, the cross-origin demos will fail: {}\n   {}",
+                second_addr,
+                e,
+                port_clash_hint(&config)
+            );
+            None
+        }
+    };
+
+    let websocket_addr = config.websocket_listen_addr();
+    let websocket_started = match websocket::serve(&websocket_addr, config.cors_origins()) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!(
+                "⚠️  Cannot listen on {}, the WebSocket demo will fail: {}\n   {}",
+                websocket_addr,
+                e,
+                port_clash_hint(&config)
+            );
+            false
+        }
+    };
+
+    println!("🚀 Rust-to-JS Transpiler Server started");
+    println!("📊 Server running on {}", config.base_url());
+    if second_listening.is_some() {
+        println!(
+            "🌐 Second origin for the CORS demos on {}",
+            config.second_origin_url()
+        );
+    }
+    if websocket_started {
+        println!("🔌 WebSocket chat on {}", config.websocket_url());
+    }
     println!("🔧 DOM API uses native JavaScript camelCase method names");
     println!("🎯 Open browser developer tools to see console output");
-    Iron::new(chain).http("localhost:3000");
+    println!("{}", summary);
+
+    // The first signal drains the requests in flight, a second one quits right away
+    let (signal_tx, signal_rx) = mpsc::channel();
+    let signalled = AtomicBool::new(false);
+    if let Err(e) = ctrlc::set_handler(move || {
+        if signalled.swap(true, Ordering::SeqCst) {
+            eprintln!("🛑 Quitting without waiting");
+            std::process::exit(130);
+        }
+        let _ = signal_tx.send(());
+    }) {
+        eprintln!(
+            "⚠️  Cannot install signal handler, Ctrl-C will not drain requests: {}",
+            e
+        );
+    }
+    let _ = signal_rx.recv();
+
+    println!(
+        "🛑 Shutting down, waiting for {} request(s) in flight (Ctrl-C again to quit now)...",
+        shutdown.in_flight()
+    );
+    let abandoned = shutdown.drain(SHUTDOWN_GRACE);
+    if abandoned > 0 {
+        eprintln!("⚠️  Gave up on {} request(s) still running", abandoned);
+        std::process::exit(1);
+    }
+    println!("👋 Bye");
+    std::process::exit(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use js_graph::JsGraph;
+
+    fn only(group: DemoGroup) -> Config {
+        Config {
+            demos: vec![group],
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn every_js_type_is_described() {
+        let types = debug_types();
+        for item in sourcemap::rust_items(sourcemap::RUST_SOURCE) {
+            if item.attr == "#[js_type]" {
+                assert!(
+                    types.iter().any(|t| t.name == item.name),
+                    "{} is missing from debug_types()",
+                    item.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_button_has_an_arm_in_run_demo() {
+        let dispatch = js_graph::dispatch_table(sourcemap::RUST_SOURCE, "run_demo");
+        let html = demo_sections(&Config::default());
+        let missing: Vec<&str> = demo_names(&html)
+            .into_iter()
+            .filter(|demo| !dispatch.contains_key(demo))
+            .collect();
+        assert!(missing.is_empty(), "no arm for {:?}", missing);
+    }
+
+    // Whatever a section's buttons call through run_demo() is in the bundle served with it
+    #[test]
+    fn buttons_reach_kept_functions() {
+        let dispatch = js_graph::dispatch_table(sourcemap::RUST_SOURCE, "run_demo");
+        let all = JsGraph::new(&JS);
+        let defined = |name: &str| all.items.iter().any(|item| item.name() == name);
+        for &group in DemoGroup::ALL {
+            let config = only(group);
+            let entry_points = entry_points(&config);
+            let bundle =
+                assets::js_bundle(BundleFormat::Script, "/runtime.js", Some(&entry_points));
+            for demo in demo_names(&demo_sections(&config)) {
+                for name in dispatch[demo].iter().filter(|name| defined(name)) {
+                    assert!(
+                        bundle.report.kept.iter().any(|kept| kept == name),
+                        "{:?}: the {:?} button needs {}, which is not in the bundle",
+                        group,
+                        demo,
+                        name
+                    );
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,215 @@
+// Prometheus text exposition of the server's counters: GET /metrics
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use iron::prelude::*;
+use iron::{Handler, StatusCode, mime};
+
+use crate::latency::LatencyStats;
+
+// Requests and bytes served, counted once each response body has been sent
+#[derive(Default)]
+pub struct RequestCounters {
+    // (route, status) -> requests
+    requests: Mutex<BTreeMap<(String, u16), u64>>,
+    // route -> response body bytes
+    bytes: Mutex<BTreeMap<String, u64>>,
+}
+
+impl RequestCounters {
+    pub fn record(&self, route: &str, status: u16, bytes: usize) {
+        *self
+            .requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry((route.to_string(), status))
+            .or_default() += 1;
+        *self
+            .bytes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(route.to_string())
+            .or_default() += bytes as u64;
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub struct MetricsEndpoint {
+    pub counters: Arc<RequestCounters>,
+    pub latency: Arc<LatencyStats>,
+    // Number of entries in the JS slice
+    pub transpiled_items: usize,
+    pub js_bundle_bytes: usize,
+}
+
+impl MetricsEndpoint {
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "# HELP mojes_sample_requests_total Requests served, by route and status."
+        );
+        let _ = writeln!(out, "# TYPE mojes_sample_requests_total counter");
+        for ((route, status), count) in self
+            .counters
+            .requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+        {
+            let _ = writeln!(
+                out,
+                "mojes_sample_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                escape_label(route),
+                status,
+                count
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP mojes_sample_response_bytes_total Response body bytes sent, by route."
+        );
+        let _ = writeln!(out, "# TYPE mojes_sample_response_bytes_total counter");
+        for (route, bytes) in self
+            .counters
+            .bytes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+        {
+            let _ = writeln!(
+                out,
+                "mojes_sample_response_bytes_total{{route=\"{}\"}} {}",
+                escape_label(route),
+                bytes
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP mojes_sample_request_duration_seconds Time to produce a response, by route."
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE mojes_sample_request_duration_seconds histogram"
+        );
+        for (route, histogram) in self.latency.snapshot() {
+            let route = escape_label(&route);
+            for (bound, count) in histogram.cumulative() {
+                let le = if bound.is_finite() {
+                    (bound / 1000.0).to_string()
+                } else {
+                    "+Inf".to_string()
+                };
+                let _ = writeln!(
+                    out,
+                    "mojes_sample_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route, le, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "mojes_sample_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                route,
+                histogram.sum_ms() / 1000.0
+            );
+            let _ = writeln!(
+                out,
+                "mojes_sample_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route,
+                histogram.count()
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP mojes_sample_transpiled_items Entries in the transpiled JS slice."
+        );
+        let _ = writeln!(out, "# TYPE mojes_sample_transpiled_items gauge");
+        let _ = writeln!(
+            out,
+            "mojes_sample_transpiled_items {}",
+            self.transpiled_items
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP mojes_sample_js_bundle_bytes Size of the JS bundle served at /app.js."
+        );
+        let _ = writeln!(out, "# TYPE mojes_sample_js_bundle_bytes gauge");
+        let _ = writeln!(out, "mojes_sample_js_bundle_bytes {}", self.js_bundle_bytes);
+
+        out
+    }
+}
+
+impl Handler for MetricsEndpoint {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let content_type = "text/plain; version=0.0.4; charset=utf-8"
+            .parse::<mime::Mime>()
+            .unwrap();
+        Ok(Response::with((
+            content_type,
+            StatusCode::OK,
+            self.render(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposition_text() {
+        let counters = Arc::new(RequestCounters::default());
+        let latency = Arc::new(LatencyStats::default());
+        counters.record("/api/echo", 200, 10);
+        counters.record("/api/echo", 200, 15);
+        counters.record("/say \"hi\"\\\n", 404, 5);
+        latency.record("/api/echo", 4.0);
+        latency.record("/api/echo", 20000.0);
+        let metrics = MetricsEndpoint {
+            counters,
+            latency,
+            transpiled_items: 42,
+            js_bundle_bytes: 1234,
+        };
+        let text = metrics.render();
+        let lines: Vec<&str> = text.lines().collect();
+
+        for expected in [
+            "mojes_sample_requests_total{route=\"/api/echo\",status=\"200\"} 2",
+            "mojes_sample_requests_total{route=\"/say \\\"hi\\\"\\\\\\n\",status=\"404\"} 1",
+            "mojes_sample_response_bytes_total{route=\"/api/echo\"} 25",
+            // Bounds in seconds, counts cumulative
+            "mojes_sample_request_duration_seconds_bucket{route=\"/api/echo\",le=\"0.0025\"} 0",
+            "mojes_sample_request_duration_seconds_bucket{route=\"/api/echo\",le=\"0.005\"} 1",
+            "mojes_sample_request_duration_seconds_bucket{route=\"/api/echo\",le=\"10\"} 1",
+            "mojes_sample_request_duration_seconds_bucket{route=\"/api/echo\",le=\"+Inf\"} 2",
+            "mojes_sample_request_duration_seconds_sum{route=\"/api/echo\"} 20.004",
+            "mojes_sample_request_duration_seconds_count{route=\"/api/echo\"} 2",
+            "mojes_sample_transpiled_items 42",
+            "mojes_sample_js_bundle_bytes 1234",
+        ] {
+            assert!(lines.contains(&expected), "missing {}\n{}", expected, text);
+        }
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|line| line.starts_with("# TYPE mojes_sample_request_duration_seconds "))
+                .count(),
+            1
+        );
+    }
+}
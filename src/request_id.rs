@@ -0,0 +1,104 @@
+// Request IDs: taken from the X-Request-Id header when the client sends a sane one,
+// generated otherwise, and echoed back so browser and server logs can be matched up.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use iron::prelude::*;
+use iron::{AfterMiddleware, BeforeMiddleware, headers, typemap};
+
+const HEADER: &str = "x-request-id";
+
+// Longer or odder IDs from clients are replaced rather than logged
+const MAX_CLIENT_ID_LEN: usize = 128;
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub struct RequestId;
+
+impl typemap::Key for RequestId {
+    type Value = String;
+}
+
+// The ID of the request, or "-" if the middleware did not run
+pub fn request_id(req: &Request) -> &str {
+    req.extensions
+        .get::<RequestId>()
+        .map(|id| id.as_str())
+        .unwrap_or("-")
+}
+
+fn generate() -> String {
+    let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+    format!("{:x}-{}", time::get_time().sec, n)
+}
+
+fn acceptable(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_CLIENT_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+pub struct RequestIds;
+
+impl RequestIds {
+    fn echo(req: &Request, res: &mut Response) {
+        if let Ok(value) = headers::HeaderValue::from_str(request_id(req)) {
+            res.headers
+                .insert(headers::HeaderName::from_static(HEADER), value);
+        }
+    }
+}
+
+impl BeforeMiddleware for RequestIds {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let id = req
+            .headers
+            .get(HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|id| acceptable(id))
+            .map(|id| id.to_string())
+            .unwrap_or_else(generate);
+        req.extensions.insert::<RequestId>(id);
+        Ok(())
+    }
+}
+
+impl AfterMiddleware for RequestIds {
+    fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+        RequestIds::echo(req, &mut res);
+        Ok(res)
+    }
+
+    fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+        RequestIds::echo(req, &mut err.response);
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_ids_must_be_short_and_plain() {
+        assert!(acceptable("3f2b8c1e-9a4d-4e2f-b6a1-0c5d7e8f9a0b"));
+        assert!(acceptable("web:req_1.2"));
+        assert!(acceptable(&"a".repeat(MAX_CLIENT_ID_LEN)));
+        assert!(!acceptable(&"a".repeat(MAX_CLIENT_ID_LEN + 1)));
+        assert!(!acceptable(""));
+        assert!(!acceptable("two words"));
+        assert!(!acceptable("line\nbreak"));
+        assert!(!acceptable("line\r\nX-Injected: 1"));
+        assert!(!acceptable("<script>"));
+        assert!(!acceptable("é"));
+    }
+
+    #[test]
+    fn generated_ids_are_acceptable_and_distinct() {
+        let (a, b) = (generate(), generate());
+        assert_ne!(a, b);
+        assert!(acceptable(&a) && acceptable(&b));
+    }
+}
@@ -0,0 +1,241 @@
+use iron::prelude::*;
+use iron::{Handler, Method, StatusCode, headers, mime, typemap};
+
+use crate::errors::json_error;
+use crate::request_id::request_id;
+
+// Pattern of the route that handled the request, for per-route statistics
+pub struct MatchedRoute;
+
+impl typemap::Key for MatchedRoute {
+    type Value = String;
+}
+
+// A route path is either an exact path ("/api/echo") or a prefix ending
+// in "/*" ("/api/*") which matches everything underneath it.
+struct Route {
+    method: Method,
+    path: String,
+    handler: Box<dyn Handler>,
+}
+
+// Does `path` match the route pattern `pattern`?
+pub fn path_matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(prefix) => path == prefix || path.starts_with(&format!("{}/", prefix)),
+        None => pattern == path,
+    }
+}
+
+// The Allow header for a path with routes for `methods`. HEAD goes with GET,
+// whose handler answers it.
+fn allow_header<'a>(methods: impl Iterator<Item = &'a Method>) -> String {
+    let mut allow: Vec<&str> = Vec::new();
+    for method in methods {
+        let names: &[&str] = if *method == Method::GET {
+            &["GET", "HEAD"]
+        } else {
+            &[method.as_str()]
+        };
+        for name in names {
+            if !allow.contains(name) {
+                allow.push(name);
+            }
+        }
+    }
+    allow.join(", ")
+}
+
+impl Route {
+    fn matches_path(&self, path: &str) -> bool {
+        path_matches(&self.path, path)
+    }
+}
+
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router { routes: Vec::new() }
+    }
+
+    pub fn route<H: Handler>(&mut self, method: Method, path: &str, handler: H) -> &mut Self {
+        self.routes.push(Route {
+            method,
+            path: path.to_string(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<H: Handler>(&mut self, path: &str, handler: H) -> &mut Self {
+        self.route(Method::GET, path, handler)
+    }
+
+    pub fn post<H: Handler>(&mut self, path: &str, handler: H) -> &mut Self {
+        self.route(Method::POST, path, handler)
+    }
+
+    // Method and path of every route, in registration order
+    pub fn routes(&self) -> impl Iterator<Item = (&Method, &str)> {
+        self.routes.iter().map(|r| (&r.method, r.path.as_str()))
+    }
+}
+
+// The request path as a single string, always starting with "/"
+pub fn request_path(req: &Request) -> String {
+    format!("/{}", req.url.path().join("/"))
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            // from_str_radix would also take a sign, as in "%+5"
+            b'%' if i + 2 < bytes.len()
+                && bytes[i + 1].is_ascii_hexdigit()
+                && bytes[i + 2].is_ascii_hexdigit() =>
+            {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                out.push(u8::from_str_radix(hex, 16).unwrap_or(b'%'));
+                i += 2;
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// Decoded key/value pairs of the query string, in order
+pub fn query_pairs(req: &Request) -> Vec<(String, String)> {
+    req.url
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|kv| !kv.is_empty())
+        .map(|kv| {
+            let (k, v) = kv.split_once('=').unwrap_or((kv, ""));
+            (percent_decode(k), percent_decode(v))
+        })
+        .collect()
+}
+
+// First value of a query parameter
+pub fn query_param(req: &Request, name: &str) -> Option<String> {
+    query_pairs(req)
+        .into_iter()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v)
+}
+
+fn not_found(path: &str) -> Response {
+    let content_type = "text/html".parse::<mime::Mime>().unwrap();
+    let data = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><title>404 Not Found</title></head>
+<body style="font-family: Arial, sans-serif; margin: 20px;">
+    <h1>404 Not Found</h1>
+    <p>Nothing is served at <code>{}</code>. Go back to the <a href="/">demo page</a>.</p>
+</body>
+</html>"#,
+        path.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    );
+    Response::with((content_type, StatusCode::NOT_FOUND, data))
+}
+
+impl Handler for Router {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let path = request_path(req);
+        let candidates: Vec<&Route> = self
+            .routes
+            .iter()
+            .filter(|r| r.matches_path(&path))
+            .collect();
+
+        // HEAD is answered by the GET handler; the body is dropped by the server
+        let wanted = if req.method == Method::HEAD {
+            Method::GET
+        } else {
+            req.method.clone()
+        };
+        if let Some(route) = candidates.iter().find(|r| r.method == wanted) {
+            req.extensions.insert::<MatchedRoute>(route.path.clone());
+            return route.handler.handle(req);
+        }
+
+        let api = path.starts_with("/api/");
+        if candidates.is_empty() {
+            if api {
+                return Ok(json_error(
+                    StatusCode::NOT_FOUND,
+                    &format!("nothing is served at {}", path),
+                    request_id(req),
+                ));
+            }
+            return Ok(not_found(&path));
+        }
+
+        // The path exists, just not with this method
+        let allow = allow_header(candidates.iter().map(|r| &r.method));
+        let message = format!("Method {} not allowed for {}", req.method, path);
+        let mut res = if api {
+            json_error(StatusCode::METHOD_NOT_ALLOWED, &message, request_id(req))
+        } else {
+            Response::with((StatusCode::METHOD_NOT_ALLOWED, format!("{}\n", message)))
+        };
+        if let Ok(value) = headers::HeaderValue::from_str(&allow) {
+            res.headers.insert(headers::ALLOW, value);
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_routes_allow_head() {
+        assert_eq!(allow_header([Method::GET].iter()), "GET, HEAD");
+        assert_eq!(
+            allow_header([Method::POST, Method::GET, Method::GET].iter()),
+            "POST, GET, HEAD"
+        );
+        assert_eq!(allow_header([Method::POST].iter()), "POST");
+    }
+
+    #[test]
+    fn percent_decode_escapes() {
+        assert_eq!(percent_decode("a%20b+c"), "a b c");
+        assert_eq!(percent_decode("%C3%A9t%c3%a9"), "été");
+        assert_eq!(percent_decode("100%25"), "100%");
+    }
+
+    #[test]
+    fn percent_decode_leaves_malformed_escapes() {
+        assert_eq!(percent_decode("%+5"), "% 5");
+        assert_eq!(percent_decode("%-1x"), "%-1x");
+        assert_eq!(percent_decode("%zz"), "%zz");
+        assert_eq!(percent_decode("50%"), "50%");
+        assert_eq!(percent_decode("%4"), "%4");
+    }
+
+    #[test]
+    fn prefix_patterns() {
+        assert!(path_matches("/api/echo", "/api/echo"));
+        assert!(!path_matches("/api/echo", "/api/echo/more"));
+        assert!(path_matches("/api/*", "/api"));
+        assert!(path_matches("/api/*", "/api/anything/below"));
+        assert!(!path_matches("/api/*", "/apis"));
+    }
+}
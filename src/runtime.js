@@ -0,0 +1,939 @@
+// Runtime prelude for mojes-transpiled code: the parts of Rust's std the
+// transpiled functions expect to find, loaded before the bundle itself.
+//
+// JS has no scopes to drop things at, so guards (MutexGuard, Ref, RefMut) are
+// released by drop(), or else at the end of the current task. Locking a Mutex
+// or borrowing a RefCell again before that is what deadlocks or panics in Rust,
+// and it panics here too. Except from a callback the browser runs in the middle
+// of a method called through the guard, as XMLHttpRequest's open() does with
+// "readystatechange": in Rust, that callback would only run later.
+//
+// Option is null-based: None is null (or undefined), anything else is Some,
+// except for the Some(None) of nested Options, which Some() wraps.
+// Result is an object, made with Ok() and Err().
+//
+// debug_repr() and debug_repr_pretty() format values as {:?} and {:#?} do,
+// using the type info registered with registerDebugTypes().
+//
+// escape_html() stands in for the Rust function of that name in src/main.rs,
+// which is not transpiled.
+
+class RustPanic extends Error {
+  constructor(message) {
+    super(message);
+    this.name = "RustPanic";
+  }
+}
+
+function panic(message) {
+  console.error(`panicked: ${message}`);
+  throw new RustPanic(message);
+}
+
+function isObject(value) {
+  return value !== null && (typeof value === "object" || typeof value === "function");
+}
+
+// ---- Dropping ----
+
+const DROP = Symbol("drop");
+
+// What a guard or a shared pointer stands in for
+const INNER = Symbol("inner");
+
+let guardsToRelease = [];
+
+function releaseAtEndOfTask(guard) {
+  if (guardsToRelease.length === 0) {
+    queueMicrotask(() => {
+      const guards = guardsToRelease;
+      guardsToRelease = [];
+      for (const guard of guards) {
+        guard[DROP]();
+      }
+    });
+  }
+  guardsToRelease.push(guard);
+}
+
+function drop(value) {
+  if (isObject(value) && typeof value[DROP] === "function") {
+    value[DROP]();
+  }
+}
+
+// A guard stands in for the value it guards: property reads, writes and method
+// calls go through to it. deref() returns the value itself, and set() replaces
+// it, which is how a guard over a number or a string is updated.
+// Method calls on the value run through `around`, for the guarded cell to know.
+function makeGuard(name, get, set, release, around = (call) => call()) {
+  let live = true;
+  const check = () => {
+    if (!live) {
+      panic(`${name} used after it was dropped`);
+    }
+  };
+  const fallbacks = {
+    deref: () => get(),
+    deref_mut: () => get(),
+    set: (value) => set(value),
+    // lock() and borrow() return the guard itself, so that Rust's .unwrap() on
+    // their LockResult is a no-op
+    unwrap: () => guard,
+    expect: () => guard,
+    valueOf: () => get(),
+    toString: () => String(get()),
+    [Symbol.toPrimitive]: () => get(),
+  };
+  const guard = new Proxy(
+    {},
+    {
+      get(_, prop) {
+        if (prop === INNER) {
+          return get();
+        }
+        if (prop === DROP) {
+          return () => {
+            if (live) {
+              live = false;
+              release();
+            }
+          };
+        }
+        check();
+        const value = get();
+        if (isObject(value) && prop in value) {
+          const member = Reflect.get(value, prop);
+          return typeof member === "function"
+            ? (...args) => around(() => member.apply(value, args))
+            : member;
+        }
+        return fallbacks[prop];
+      },
+      set(_, prop, newValue) {
+        check();
+        const value = get();
+        if (!isObject(value)) {
+          panic(`cannot set ${String(prop)} through a ${name} to a ${typeof value}`);
+        }
+        return Reflect.set(value, prop, newValue);
+      },
+      has(_, prop) {
+        const value = get();
+        return isObject(value) && prop in value;
+      },
+    },
+  );
+  return guard;
+}
+
+// For the `around` of makeGuard(): `cell` is lent to whatever the call runs
+// before it returns, and counts how many such calls are in progress
+function lentDuring(cell) {
+  return (call) => {
+    cell.lent += 1;
+    try {
+      return call();
+    } finally {
+      cell.lent -= 1;
+    }
+  };
+}
+
+// ---- Option and Result ----
+
+const None = null;
+
+// Some(None), Some(Some(None)) and so on: `depth` Somes around a None
+class NestedNone {
+  constructor(depth) {
+    this.depth = depth;
+  }
+}
+
+function Some(value) {
+  if (value === null || value === undefined) {
+    return new NestedNone(1);
+  }
+  if (value instanceof NestedNone) {
+    return new NestedNone(value.depth + 1);
+  }
+  return value;
+}
+
+// What a Some holds
+function someValue(option) {
+  if (option instanceof NestedNone) {
+    return option.depth === 1 ? null : new NestedNone(option.depth - 1);
+  }
+  return option;
+}
+
+const Option = {
+  is_some: (option) => option !== null && option !== undefined,
+  is_none: (option) => option === null || option === undefined,
+  unwrap: (option) =>
+    Option.is_some(option)
+      ? someValue(option)
+      : panic("called `Option::unwrap()` on a `None` value"),
+  expect: (option, message) => (Option.is_some(option) ? someValue(option) : panic(message)),
+  unwrap_or: (option, fallback) => (Option.is_some(option) ? someValue(option) : fallback),
+  unwrap_or_else: (option, f) => (Option.is_some(option) ? someValue(option) : f()),
+  map: (option, f) => (Option.is_some(option) ? Some(f(someValue(option))) : null),
+  and_then: (option, f) => (Option.is_some(option) ? f(someValue(option)) : null),
+  filter: (option, predicate) =>
+    Option.is_some(option) && predicate(someValue(option)) ? option : null,
+  ok_or: (option, error) => (Option.is_some(option) ? Ok(someValue(option)) : Err(error)),
+};
+
+class Result {
+  #ok;
+  #value;
+
+  constructor(ok, value) {
+    this.#ok = ok;
+    this.#value = value;
+  }
+
+  is_ok() {
+    return this.#ok;
+  }
+
+  is_err() {
+    return !this.#ok;
+  }
+
+  ok() {
+    return this.#ok ? this.#value : null;
+  }
+
+  err() {
+    return this.#ok ? null : this.#value;
+  }
+
+  unwrap() {
+    if (!this.#ok) {
+      panic(`called \`Result::unwrap()\` on an \`Err\` value: ${debug_repr(this.#value)}`);
+    }
+    return this.#value;
+  }
+
+  expect(message) {
+    if (!this.#ok) {
+      panic(`${message}: ${debug_repr(this.#value)}`);
+    }
+    return this.#value;
+  }
+
+  unwrap_err() {
+    if (this.#ok) {
+      panic(`called \`Result::unwrap_err()\` on an \`Ok\` value: ${debug_repr(this.#value)}`);
+    }
+    return this.#value;
+  }
+
+  unwrap_or(fallback) {
+    return this.#ok ? this.#value : fallback;
+  }
+
+  unwrap_or_else(f) {
+    return this.#ok ? this.#value : f(this.#value);
+  }
+
+  map(f) {
+    return this.#ok ? Ok(f(this.#value)) : this;
+  }
+
+  map_err(f) {
+    return this.#ok ? this : Err(f(this.#value));
+  }
+
+  and_then(f) {
+    return this.#ok ? f(this.#value) : this;
+  }
+}
+
+function Ok(value) {
+  return new Result(true, value);
+}
+
+function Err(error) {
+  return new Result(false, error);
+}
+
+// ---- Rc and Arc ----
+
+const COUNTS = Symbol("counts");
+
+// Shared ownership: clone() hands out another pointer to the same value and
+// counts it. Anything else is looked up on the value, as Rust auto-derefs.
+function makeSharedPointer() {
+  const Pointer = class {
+    constructor(value, counts = { strong: 1 }) {
+      this[INNER] = value;
+      this[COUNTS] = counts;
+      return new Proxy(this, {
+        get(target, prop) {
+          if (prop === INNER || prop === COUNTS || prop === "clone" || prop === DROP) {
+            return Reflect.get(target, prop);
+          }
+          const value = target[INNER];
+          if (isObject(value)) {
+            const member = Reflect.get(value, prop);
+            return typeof member === "function" ? member.bind(value) : member;
+          }
+          if (prop === Symbol.toPrimitive || prop === "valueOf") {
+            return () => value;
+          }
+          if (prop === "toString") {
+            return () => String(value);
+          }
+          return undefined;
+        },
+      });
+    }
+
+    static new(value) {
+      return new this(value);
+    }
+
+    static clone(pointer) {
+      return pointer.clone();
+    }
+
+    static strong_count(pointer) {
+      return pointer[COUNTS].strong;
+    }
+
+    static ptr_eq(a, b) {
+      return a[COUNTS] === b[COUNTS];
+    }
+
+    clone() {
+      this[COUNTS].strong += 1;
+      return new Pointer(this[INNER], this[COUNTS]);
+    }
+
+    [DROP]() {
+      this[COUNTS].strong -= 1;
+    }
+  };
+  return Pointer;
+}
+
+const Rc = makeSharedPointer();
+const Arc = makeSharedPointer();
+
+// ---- Mutex ----
+
+class Mutex {
+  constructor(value) {
+    this.value = value;
+    this.locked = false;
+    // Method calls through the guard in progress
+    this.lent = 0;
+  }
+
+  static new(value) {
+    return new Mutex(value);
+  }
+
+  lock() {
+    if (this.locked && this.lent === 0) {
+      panic("Mutex locked again while its guard is alive, which deadlocks in Rust");
+    }
+    return this.guard();
+  }
+
+  try_lock() {
+    return this.locked && this.lent === 0 ? Err("WouldBlock") : Ok(this.guard());
+  }
+
+  into_inner() {
+    return Ok(this.value);
+  }
+
+  // While lent to a callback, the lock stays with the guard it was lent by
+  guard() {
+    const lent = this.locked;
+    this.locked = true;
+    const guard = makeGuard(
+      "MutexGuard",
+      () => this.value,
+      (value) => {
+        this.value = value;
+      },
+      () => {
+        if (!lent) {
+          this.locked = false;
+        }
+      },
+      lentDuring(this),
+    );
+    releaseAtEndOfTask(guard);
+    return guard;
+  }
+}
+
+// ---- Cell and RefCell ----
+
+class Cell {
+  constructor(value) {
+    this.value = value;
+  }
+
+  static new(value) {
+    return new Cell(value);
+  }
+
+  get() {
+    return this.value;
+  }
+
+  set(value) {
+    this.value = value;
+  }
+
+  replace(value) {
+    const old = this.value;
+    this.value = value;
+    return old;
+  }
+
+  // For Cell<Option<T>>, the one Default we can know about
+  take() {
+    return this.replace(null);
+  }
+
+  into_inner() {
+    return this.value;
+  }
+}
+
+class RefCell {
+  constructor(value) {
+    this.value = value;
+    // Number of shared borrows, or -1 while mutably borrowed
+    this.borrows = 0;
+    // Method calls through a guard in progress
+    this.lent = 0;
+  }
+
+  static new(value) {
+    return new RefCell(value);
+  }
+
+  borrow() {
+    if (this.borrows < 0 && this.lent === 0) {
+      panic("already mutably borrowed: BorrowError");
+    }
+    return this.guard("Ref", 1);
+  }
+
+  borrow_mut() {
+    if (this.borrows !== 0 && this.lent === 0) {
+      panic("already borrowed: BorrowMutError");
+    }
+    return this.guard("RefMut", -1);
+  }
+
+  try_borrow() {
+    return this.borrows < 0 && this.lent === 0 ? Err("BorrowError") : Ok(this.guard("Ref", 1));
+  }
+
+  try_borrow_mut() {
+    return this.borrows !== 0 && this.lent === 0
+      ? Err("BorrowMutError")
+      : Ok(this.guard("RefMut", -1));
+  }
+
+  replace(value) {
+    if (this.borrows !== 0) {
+      panic("already borrowed: BorrowMutError");
+    }
+    const old = this.value;
+    this.value = value;
+    return old;
+  }
+
+  into_inner() {
+    return this.value;
+  }
+
+  // A borrow lent to a callback is counted as the lender's
+  guard(name, borrow) {
+    const counted = this.lent === 0;
+    if (counted) {
+      this.borrows = borrow < 0 ? -1 : this.borrows + 1;
+    }
+    const guard = makeGuard(
+      name,
+      () => this.value,
+      (value) => {
+        if (borrow > 0) {
+          panic("cannot assign through a Ref");
+        }
+        this.value = value;
+      },
+      () => {
+        if (counted) {
+          this.borrows = borrow < 0 ? 0 : this.borrows - 1;
+        }
+      },
+      lentDuring(this),
+    );
+    releaseAtEndOfTask(guard);
+    return guard;
+  }
+}
+
+// ---- Vec ----
+
+// Vecs are plain arrays. The Rust methods arrays lack are functions here, taking
+// the array first, as in Vec.get(items, 2): Array.prototype is left alone.
+const Vec = {
+  new: () => [],
+  with_capacity: () => [],
+  from: (iterable) => Array.from(iterable),
+  len(vec) {
+    return vec.length;
+  },
+  is_empty(vec) {
+    return vec.length === 0;
+  },
+  get(vec, index) {
+    return index < vec.length ? vec[index] : null;
+  },
+  first(vec) {
+    return vec.length > 0 ? vec[0] : null;
+  },
+  last(vec) {
+    return vec.length > 0 ? vec[vec.length - 1] : null;
+  },
+  contains(vec, value) {
+    return vec.includes(value);
+  },
+  iter(vec) {
+    return vec;
+  },
+  enumerate(vec) {
+    return vec.map((value, index) => [index, value]);
+  },
+  insert(vec, index, value) {
+    if (index > vec.length) {
+      panic(`insertion index (is ${index}) should be <= len (is ${vec.length})`);
+    }
+    vec.splice(index, 0, value);
+  },
+  remove(vec, index) {
+    if (index >= vec.length) {
+      panic(`removal index (is ${index}) should be < len (is ${vec.length})`);
+    }
+    return vec.splice(index, 1)[0];
+  },
+  swap_remove(vec, index) {
+    if (index >= vec.length) {
+      panic(`swap_remove index (is ${index}) should be < len (is ${vec.length})`);
+    }
+    const value = vec[index];
+    vec[index] = vec[vec.length - 1];
+    vec.pop();
+    return value;
+  },
+  truncate(vec, len) {
+    if (len < vec.length) {
+      vec.length = len;
+    }
+  },
+  clear(vec) {
+    vec.length = 0;
+  },
+  extend(vec, iterable) {
+    for (const value of iterable) {
+      vec.push(value);
+    }
+  },
+  retain(vec, predicate) {
+    let kept = 0;
+    for (const value of vec) {
+      if (predicate(value)) {
+        vec[kept++] = value;
+      }
+    }
+    vec.length = kept;
+  },
+  dedup(vec) {
+    let kept = 0;
+    for (let i = 0; i < vec.length; i++) {
+      if (kept === 0 || vec[i] !== vec[kept - 1]) {
+        vec[kept++] = vec[i];
+      }
+    }
+    vec.length = kept;
+  },
+};
+
+// ---- HashMap and HashSet ----
+
+// Keys are compared the JS way: by value for numbers and strings,
+// by identity for objects
+class HashMap extends Map {
+  static new() {
+    return new HashMap();
+  }
+
+  insert(key, value) {
+    const old = this.has(key) ? super.get(key) : null;
+    this.set(key, value);
+    return old;
+  }
+
+  get(key) {
+    return this.has(key) ? super.get(key) : null;
+  }
+
+  contains_key(key) {
+    return this.has(key);
+  }
+
+  remove(key) {
+    const old = this.get(key);
+    this.delete(key);
+    return old;
+  }
+
+  len() {
+    return this.size;
+  }
+
+  is_empty() {
+    return this.size === 0;
+  }
+
+  iter() {
+    return Array.from(this.entries());
+  }
+
+  extend(pairs) {
+    for (const [key, value] of pairs) {
+      this.set(key, value);
+    }
+  }
+
+  entry(key) {
+    const map = this;
+    return {
+      or_insert(value) {
+        if (!map.has(key)) {
+          map.set(key, value);
+        }
+        return map.get(key);
+      },
+      or_insert_with(f) {
+        if (!map.has(key)) {
+          map.set(key, f());
+        }
+        return map.get(key);
+      },
+      and_modify(f) {
+        if (map.has(key)) {
+          const modified = f(map.get(key));
+          if (modified !== undefined) {
+            map.set(key, modified);
+          }
+        }
+        return this;
+      },
+    };
+  }
+}
+
+class HashSet extends Set {
+  static new() {
+    return new HashSet();
+  }
+
+  insert(value) {
+    const added = !this.has(value);
+    this.add(value);
+    return added;
+  }
+
+  contains(value) {
+    return this.has(value);
+  }
+
+  remove(value) {
+    return this.delete(value);
+  }
+
+  len() {
+    return this.size;
+  }
+
+  is_empty() {
+    return this.size === 0;
+  }
+
+  iter() {
+    return Array.from(this.values());
+  }
+
+  extend(values) {
+    for (const value of values) {
+      this.add(value);
+    }
+  }
+
+  union(other) {
+    return Array.from(new Set([...this, ...other]));
+  }
+
+  intersection(other) {
+    return Array.from(this).filter((value) => other.has(value));
+  }
+
+  difference(other) {
+    return Array.from(this).filter((value) => !other.has(value));
+  }
+}
+
+// ---- HTML ----
+
+// Text as HTML that shows it as is, for markup built with format!()
+function escape_html(text) {
+  return String(text)
+    .replace(/&/g, "&amp;")
+    .replace(/</g, "&lt;")
+    .replace(/>/g, "&gt;")
+    .replace(/"/g, "&quot;")
+    .replace(/'/g, "&#39;");
+}
+
+// ---- Debug formatting ----
+
+// JS values do not tell a u32 from an f64, or an Option from what it holds,
+// so #[js_type] types are described to the formatter by the server:
+//   registerDebugTypes({ Person: { struct: { named: [["name", "str"], ["age", "int"]] } } })
+// A type is { struct: fields } or { enum: [[variant, fields], ...] }, where fields
+// is null, { tuple: [ty, ...] } or { named: [[field, ty], ...] }. A ty is one of
+// "bool", "int", "float", "char", "str", or { option: ty }, { vec: ty },
+// { tuple: [ty, ...] }, { type: name }.
+// Enum values are the variant name for unit variants, and { Variant: payload }
+// otherwise, as serde_json has them.
+const debugTypes = new Map();
+
+function registerDebugTypes(types) {
+  for (const [name, type] of Object.entries(types)) {
+    debugTypes.set(name, type);
+  }
+}
+
+function debug_repr(value) {
+  return debugAny(value, false);
+}
+
+function debug_repr_pretty(value) {
+  return debugAny(value, true);
+}
+
+// Each item of a pretty-printed list on lines of its own, indented
+function debugItems(open, items, close, pretty) {
+  if (items.length === 0) {
+    return open + close;
+  }
+  if (!pretty) {
+    return open + items.join(", ") + close;
+  }
+  const lines = items.map((item) => `    ${item.replace(/\n/g, "\n    ")},\n`);
+  return `${open}\n${lines.join("")}${close}`;
+}
+
+function debugStruct(name, fields, pretty) {
+  if (fields.length === 0) {
+    return name;
+  }
+  const items = fields.map(([field, value]) => `${field}: ${value}`);
+  return pretty ? debugItems(`${name} {`, items, "}", true) : `${name} { ${items.join(", ")} }`;
+}
+
+function debugTuple(name, items, pretty) {
+  if (name !== "" && items.length === 0) {
+    return name;
+  }
+  if (name === "" && items.length === 1 && !pretty) {
+    return `(${items[0]},)`;
+  }
+  return debugItems(`${name}(`, items, ")", pretty);
+}
+
+const NOT_PRINTABLE = /[\p{Cc}\p{Cf}\p{Cs}\p{Co}\p{Cn}\p{Zl}\p{Zp}\p{Zs}\p{Grapheme_Extend}]/u;
+
+function debugChars(text, quote) {
+  let escaped = "";
+  for (const c of text) {
+    if (c === quote || c === "\\") {
+      escaped += `\\${c}`;
+    } else if (c === "\n") {
+      escaped += "\\n";
+    } else if (c === "\r") {
+      escaped += "\\r";
+    } else if (c === "\t") {
+      escaped += "\\t";
+    } else if (c === "\0") {
+      escaped += "\\0";
+    } else if (c !== " " && NOT_PRINTABLE.test(c)) {
+      escaped += `\\u{${c.codePointAt(0).toString(16)}}`;
+    } else {
+      escaped += c;
+    }
+  }
+  return quote + escaped + quote;
+}
+
+// Shortest round-tripping digits, as in Rust; exponential outside 1e-4..1e16
+function debugFloat(x) {
+  if (Number.isNaN(x)) {
+    return "NaN";
+  }
+  if (!Number.isFinite(x)) {
+    return x > 0 ? "inf" : "-inf";
+  }
+  if (x === 0) {
+    return Object.is(x, -0) ? "-0.0" : "0.0";
+  }
+  const abs = Math.abs(x);
+  if (abs < 1e-4 || abs >= 1e16) {
+    return x.toExponential().replace("e+", "e");
+  }
+  const text = String(x);
+  return text.includes(".") ? text : `${text}.0`;
+}
+
+// Guards and shared pointers format as what they point to
+function debugDeref(value) {
+  while (isObject(value) && value[INNER] !== undefined) {
+    value = value[INNER];
+  }
+  return value;
+}
+
+function debugTyped(value, ty, pretty) {
+  value = debugDeref(value);
+  switch (ty) {
+    case "bool":
+    case "int":
+      return String(value);
+    case "float":
+      return debugFloat(Number(value));
+    case "char":
+      return debugChars(String(value), "'");
+    case "str":
+      return debugChars(String(value), '"');
+  }
+  if ("option" in ty) {
+    return Option.is_none(value)
+      ? "None"
+      : debugTuple("Some", [debugTyped(someValue(value), ty.option, pretty)], pretty);
+  }
+  if ("vec" in ty) {
+    return debugItems("[", Array.from(value, (item) => debugTyped(item, ty.vec, pretty)), "]", pretty);
+  }
+  if ("tuple" in ty) {
+    return debugTuple("", ty.tuple.map((itemTy, i) => debugTyped(value[i], itemTy, pretty)), pretty);
+  }
+  const type = debugTypes.get(ty.type);
+  return type ? debugNamed(ty.type, type, value, pretty) : debugAny(value, pretty);
+}
+
+function debugFields(name, fields, value, pretty) {
+  if (fields === null) {
+    return name;
+  }
+  if ("tuple" in fields) {
+    const items = Array.isArray(value) ? value : [value];
+    return debugTuple(
+      name,
+      fields.tuple.map((ty, i) => debugTyped(fields.tuple.length === 1 ? value : items[i], ty, pretty)),
+      pretty,
+    );
+  }
+  return debugStruct(
+    name,
+    fields.named.map(([field, ty]) => [field, debugTyped(value[field], ty, pretty)]),
+    pretty,
+  );
+}
+
+function debugNamed(name, type, value, pretty) {
+  if ("struct" in type) {
+    return debugFields(name, type.struct, value, pretty);
+  }
+  const variant = typeof value === "string" ? value : Object.keys(value)[0];
+  const variantType = type.enum.find(([candidate]) => candidate === variant);
+  if (!variantType) {
+    return `${name}::<unknown variant ${debugChars(String(variant), '"')}>`;
+  }
+  return debugFields(variant, variantType[1], typeof value === "string" ? null : value[variant], pretty);
+}
+
+// Without type info: integers print as such, whatever their Rust type,
+// and objects of unregistered classes as structs of their own properties
+function debugAny(value, pretty) {
+  value = debugDeref(value);
+  if (value === null || value === undefined) {
+    return "None";
+  }
+  if (value instanceof NestedNone) {
+    return debugTuple("Some", [debugAny(someValue(value), pretty)], pretty);
+  }
+  switch (typeof value) {
+    case "boolean":
+    case "bigint":
+      return String(value);
+    case "number":
+      return Number.isSafeInteger(value) ? String(value) : debugFloat(value);
+    case "string":
+      return debugChars(value, '"');
+    case "function":
+      return value.name || "<function>";
+  }
+  if (Array.isArray(value)) {
+    return debugItems("[", value.map((item) => debugAny(item, pretty)), "]", pretty);
+  }
+  if (value instanceof Result) {
+    return value.is_ok()
+      ? debugTuple("Ok", [debugAny(value.unwrap(), pretty)], pretty)
+      : debugTuple("Err", [debugAny(value.unwrap_err(), pretty)], pretty);
+  }
+  if (value instanceof Map) {
+    const entries = Array.from(value, ([k, v]) => `${debugAny(k, pretty)}: ${debugAny(v, pretty)}`);
+    return debugItems("{", entries, "}", pretty);
+  }
+  if (value instanceof Set) {
+    return debugItems("{", Array.from(value, (item) => debugAny(item, pretty)), "}", pretty);
+  }
+  if (value instanceof Mutex) {
+    const data = value.locked ? "<locked>" : debugAny(value.value, pretty);
+    const fields = debugStruct("Mutex", [["data", data], ["poisoned", "false"]], pretty);
+    return pretty ? fields.replace(/\}$/, "    ..\n}") : fields.replace(/ \}$/, ", .. }");
+  }
+  if (value instanceof RefCell) {
+    const data = value.borrows < 0 ? "<borrowed>" : debugAny(value.value, pretty);
+    return debugStruct("RefCell", [["value", data]], pretty);
+  }
+  if (value instanceof Cell) {
+    return debugStruct("Cell", [["value", debugAny(value.value, pretty)]], pretty);
+  }
+  const name = value.constructor && value.constructor !== Object ? value.constructor.name : "";
+  const type = debugTypes.get(name);
+  if (type) {
+    return debugNamed(name, type, value, pretty);
+  }
+  const fields = Object.entries(value).map(([field, v]) => [field, debugAny(v, pretty)]);
+  if (name === "") {
+    const entries = fields.map(([field, v]) => `${debugChars(field, '"')}: ${v}`);
+    return debugItems("{", entries, "}", pretty);
+  }
+  return debugStruct(name, fields, pretty);
+}
@@ -0,0 +1,181 @@
+// The JS runtime prelude the transpiled code runs on: Rc, Arc, Mutex, Cell,
+// RefCell, Option, Result, Vec, HashMap and HashSet, as the transpiled code
+// expects to find them. The source is runtime.js; bump VERSION with it.
+
+use crate::config::BundleFormat;
+
+pub const VERSION: &str = "2.2.0";
+
+const SOURCE: &str = include_str!("runtime.js");
+
+// runtime.js with its version. As a classic script it reports the version as
+// mojesRuntime.version; as an ES module it exports everything it declares instead.
+pub fn runtime_js(format: BundleFormat) -> String {
+    let footer = match format {
+        BundleFormat::Script => {
+            "globalThis.mojesRuntime = { version: MOJES_RUNTIME_VERSION };\n".to_string()
+        }
+        BundleFormat::Module => format!("export {{ {} }};\n", exports().join(", ")),
+    };
+    format!(
+        "// mojes runtime prelude {}\nconst MOJES_RUNTIME_VERSION = \"{}\";\n\n{}\n{}",
+        VERSION, VERSION, SOURCE, footer
+    )
+}
+
+// Functions, classes and constants declared at the top level of runtime.js
+pub fn exports() -> Vec<&'static str> {
+    let mut names = vec!["MOJES_RUNTIME_VERSION"];
+    for line in SOURCE.lines() {
+        let Some(rest) = ["function ", "class ", "const "]
+            .iter()
+            .find_map(|keyword| line.strip_prefix(keyword))
+        else {
+            continue;
+        };
+        let name = rest
+            .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+            .next()
+            .unwrap_or("");
+        if !name.is_empty() {
+            names.push(name);
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::{self, Write};
+    use std::process::{Command, Stdio};
+
+    use super::*;
+
+    // Run `script` with node after the runtime, as a classic script, and return its output.
+    // Without node on the PATH, say the test is skipped and return None.
+    pub fn run_node(script: &str) -> Option<String> {
+        let spawned = Command::new("node")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut node = match spawned {
+            Ok(node) => node,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                eprintln!("skipped: node is not on the PATH");
+                return None;
+            }
+            Err(e) => panic!("cannot run node: {}", e),
+        };
+        let source = format!("{}\n{}", runtime_js(BundleFormat::Script), script);
+        node.stdin
+            .take()
+            .unwrap()
+            .write_all(source.as_bytes())
+            .unwrap();
+        let output = node.wait_with_output().unwrap();
+        assert!(
+            output.status.success(),
+            "node failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Some(String::from_utf8(output.stdout).unwrap())
+    }
+
+    #[test]
+    fn mutex_is_lent_to_callbacks_of_calls_through_its_guard() {
+        // open() fires readystatechange before it returns, as XMLHttpRequest's does
+        let Some(output) = run_node(
+            r#"
+            class Request {
+              constructor() { this.listeners = []; this.readyState = 0; }
+              addEventListener(_, listener) { this.listeners.push(listener); }
+              open() { this.readyState = 1; this.listeners.forEach((listener) => listener()); }
+            }
+            const shared = Arc.new(Mutex.new(new Request()));
+            const request = shared.lock().unwrap();
+            const shared1 = shared.clone();
+            request.addEventListener("readystatechange", () => {
+              console.log("readyState " + shared1.lock().unwrap().readyState);
+            });
+            request.open("GET", "/");
+            try {
+              shared.lock();
+            } catch (e) {
+              console.log(e.message);
+            }
+            drop(request);
+            console.log(shared.try_lock().is_ok());
+            "#,
+        ) else {
+            return;
+        };
+        assert_eq!(
+            output,
+            "readyState 1\n\
+             Mutex locked again while its guard is alive, which deadlocks in Rust\n\
+             true\n"
+        );
+    }
+
+    #[test]
+    fn refcell_is_lent_like_mutex() {
+        let Some(output) = run_node(
+            r#"
+            const cell = RefCell.new({ callback: null, fire() { this.callback(); } });
+            const borrowed = cell.borrow_mut();
+            borrowed.callback = () => console.log(cell.try_borrow_mut().is_ok());
+            borrowed.fire();
+            console.log(cell.try_borrow().is_ok());
+            drop(borrowed);
+            console.log(cell.try_borrow_mut().is_ok());
+            "#,
+        ) else {
+            return;
+        };
+        assert_eq!(output, "true\nfalse\ntrue\n");
+    }
+
+    #[test]
+    fn vec_helpers_leave_arrays_alone() {
+        let Some(output) = run_node(
+            r#"
+            const items = Vec.from([3, 1, 1, 2]);
+            Vec.dedup(items);
+            Vec.insert(items, 0, 0);
+            console.log(JSON.stringify(items), Vec.len(items), Vec.get(items, 9), Vec.last(items));
+            console.log(["len", "get", "first", "insert", "remove"].some((name) => name in []));
+            "#,
+        ) else {
+            return;
+        };
+        assert_eq!(output, "[0,3,1,2] 4 null 2\nfalse\n");
+    }
+
+    #[test]
+    fn escape_html_matches_rust() {
+        let text = r#"<b title="x">Tom & 'Jerry'</b>"#;
+        let Some(output) = run_node(&format!(
+            "console.log(escape_html({}));",
+            serde_json::to_string(text).unwrap()
+        )) else {
+            return;
+        };
+        assert_eq!(output, format!("{}\n", crate::escape_html(text)));
+    }
+
+    #[test]
+    fn exports_are_top_level_declarations() {
+        let exports = exports();
+        for name in [
+            "MOJES_RUNTIME_VERSION",
+            "Mutex",
+            "Vec",
+            "debug_repr",
+            "None",
+        ] {
+            assert!(exports.contains(&name), "{}", name);
+        }
+        assert!(!exports.contains(&"guardsToRelease"));
+    }
+}
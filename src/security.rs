@@ -0,0 +1,226 @@
+// Security headers on every response, and CORS for the cross-origin XHR demos.
+//
+// The server also listens on a second port, which is a different origin as far
+// as the browser is concerned: fetch_with_credentials against it goes through a
+// real preflight, and only the origins configured in [cors] get an answer.
+
+use iron::prelude::*;
+use iron::{AfterMiddleware, AroundMiddleware, Handler, Method, StatusCode, headers};
+
+use crate::config::{CorsConfig, SecurityHeadersConfig};
+
+// Response headers the demos read, which browsers hide from cross-origin callers unless told
+const EXPOSED_HEADERS: &str = "x-request-id, server-timing";
+
+const ALLOWED_METHODS: &str = "GET, HEAD, POST, OPTIONS";
+
+pub struct SecurityHeaders {
+    frame_options: Option<headers::HeaderValue>,
+    referrer_policy: Option<headers::HeaderValue>,
+}
+
+impl SecurityHeaders {
+    // Empty settings leave the corresponding header out
+    pub fn new(config: &SecurityHeadersConfig) -> Result<Self, String> {
+        fn value(name: &str, v: &str) -> Result<Option<headers::HeaderValue>, String> {
+            if v.is_empty() {
+                return Ok(None);
+            }
+            headers::HeaderValue::from_str(v)
+                .map(Some)
+                .map_err(|e| format!("invalid {} {:?}: {}", name, v, e))
+        }
+        Ok(SecurityHeaders {
+            frame_options: value("frame_options", &config.frame_options)?,
+            referrer_policy: value("referrer_policy", &config.referrer_policy)?,
+        })
+    }
+
+    fn apply(&self, res: &mut Response) {
+        res.headers.insert(
+            headers::X_CONTENT_TYPE_OPTIONS,
+            headers::HeaderValue::from_static("nosniff"),
+        );
+        if let Some(value) = &self.frame_options {
+            res.headers.insert(headers::X_FRAME_OPTIONS, value.clone());
+        }
+        if let Some(value) = &self.referrer_policy {
+            res.headers.insert(headers::REFERRER_POLICY, value.clone());
+        }
+    }
+}
+
+impl AfterMiddleware for SecurityHeaders {
+    fn after(&self, _: &mut Request, mut res: Response) -> IronResult<Response> {
+        self.apply(&mut res);
+        Ok(res)
+    }
+
+    fn catch(&self, _: &mut Request, mut err: IronError) -> IronResult<Response> {
+        self.apply(&mut err.response);
+        Err(err)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cors {
+    allowed_origins: Vec<String>,
+    allowed_headers: String,
+    allow_credentials: bool,
+    max_age_secs: u64,
+}
+
+impl Cors {
+    pub fn new(allowed_origins: Vec<String>, config: &CorsConfig) -> Self {
+        Cors {
+            allowed_origins,
+            allowed_headers: config.allowed_headers.join(", "),
+            allow_credentials: config.allow_credentials,
+            max_age_secs: config.max_age_secs,
+        }
+    }
+
+    // Access-Control-Allow-Origin for a request from `origin`, if we answer it at all:
+    // the origin itself when it is listed, else "*" when any origin is allowed
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        if self.allowed_origins.iter().any(|allowed| allowed == origin) {
+            Some(origin.to_string())
+        } else if self.allowed_origins.iter().any(|allowed| allowed == "*") {
+            Some("*".to_string())
+        } else {
+            None
+        }
+    }
+
+    fn allowed_origin(&self, req: &Request) -> Option<String> {
+        let origin = req.headers.get(headers::ORIGIN)?.to_str().ok()?;
+        self.allow_origin(origin)
+    }
+
+    fn insert(res: &mut Response, name: headers::HeaderName, value: &str) {
+        if let Ok(value) = headers::HeaderValue::from_str(value) {
+            res.headers.insert(name, value);
+        }
+    }
+
+    // Listed origins are echoed, and may send credentials. Any other origin only gets
+    // a literal "*", which browsers refuse for credentialed requests: echoing it with
+    // credentials would let every site read responses with the user's cookies.
+    fn apply(&self, req: &Request, res: &mut Response) {
+        if !req.headers.contains_key(headers::ORIGIN) {
+            return;
+        }
+        res.headers
+            .append(headers::VARY, headers::HeaderValue::from_static("origin"));
+        let Some(origin) = self.allowed_origin(req) else {
+            return;
+        };
+        Cors::insert(res, headers::ACCESS_CONTROL_ALLOW_ORIGIN, &origin);
+        if self.allow_credentials && origin != "*" {
+            Cors::insert(res, headers::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+        }
+        Cors::insert(res, headers::ACCESS_CONTROL_EXPOSE_HEADERS, EXPOSED_HEADERS);
+    }
+
+    fn is_preflight(req: &Request) -> bool {
+        req.method == Method::OPTIONS
+            && req.headers.contains_key(headers::ORIGIN)
+            && req
+                .headers
+                .contains_key(headers::ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    // Origins we do not know get a 403 without any CORS headers, which the browser reports
+    fn preflight(&self, req: &Request) -> Response {
+        if self.allowed_origin(req).is_none() {
+            return Response::with(StatusCode::FORBIDDEN);
+        }
+        let mut res = Response::with(StatusCode::NO_CONTENT);
+        self.apply(req, &mut res);
+        Cors::insert(
+            &mut res,
+            headers::ACCESS_CONTROL_ALLOW_METHODS,
+            ALLOWED_METHODS,
+        );
+        Cors::insert(
+            &mut res,
+            headers::ACCESS_CONTROL_ALLOW_HEADERS,
+            &self.allowed_headers,
+        );
+        Cors::insert(
+            &mut res,
+            headers::ACCESS_CONTROL_MAX_AGE,
+            &self.max_age_secs.to_string(),
+        );
+        res
+    }
+}
+
+struct CorsHandler {
+    cors: Cors,
+    handler: Box<dyn Handler>,
+}
+
+impl Handler for CorsHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        if Cors::is_preflight(req) {
+            return Ok(self.cors.preflight(req));
+        }
+        match self.handler.handle(req) {
+            Ok(mut res) => {
+                self.cors.apply(req, &mut res);
+                Ok(res)
+            }
+            Err(mut err) => {
+                self.cors.apply(req, &mut err.response);
+                Err(err)
+            }
+        }
+    }
+}
+
+// Around the router, so preflights are answered without a route for OPTIONS
+impl AroundMiddleware for Cors {
+    fn around(self, handler: Box<dyn Handler>) -> Box<dyn Handler> {
+        Box::new(CorsHandler {
+            cors: self,
+            handler,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors(origins: &[&str]) -> Cors {
+        Cors::new(
+            origins.iter().map(|o| o.to_string()).collect(),
+            &CorsConfig::default(),
+        )
+    }
+
+    #[test]
+    fn listed_origins_are_echoed() {
+        let cors = cors(&["http://localhost:3000", "http://127.0.0.1:3000"]);
+        assert_eq!(
+            cors.allow_origin("http://127.0.0.1:3000").as_deref(),
+            Some("http://127.0.0.1:3000")
+        );
+        assert_eq!(cors.allow_origin("http://localhost:3001"), None);
+        assert_eq!(cors.allow_origin("http://evil.example"), None);
+    }
+
+    #[test]
+    fn wildcard_is_answered_literally() {
+        let cors = cors(&["*", "http://localhost:3000"]);
+        assert_eq!(
+            cors.allow_origin("http://evil.example").as_deref(),
+            Some("*")
+        );
+        assert_eq!(
+            cors.allow_origin("http://localhost:3000").as_deref(),
+            Some("http://localhost:3000")
+        );
+    }
+}
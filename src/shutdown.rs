@@ -0,0 +1,176 @@
+// Graceful shutdown: count requests in flight, and once draining starts,
+// turn new ones away so the ones already running can finish.
+//
+// A request stays in flight until its body has been written, not just until the
+// handler returns: streamed bodies (slow downloads, throttled faults) keep
+// writing long after that.
+
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use iron::prelude::*;
+use iron::response::WriteBody;
+use iron::{Handler, StatusCode, headers};
+
+#[derive(Default)]
+pub struct Shutdown {
+    in_flight: AtomicUsize,
+    draining: AtomicBool,
+}
+
+// One request in flight, until dropped
+struct InFlight(Arc<Shutdown>);
+
+impl InFlight {
+    fn start(state: &Arc<Shutdown>) -> Self {
+        state.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(state.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Stands in for the response body and ends the request once it has been written,
+// or once it is dropped unwritten (HEAD, dropped connections)
+struct TrackedBody {
+    inner: Option<Box<dyn WriteBody>>,
+    in_flight: Option<InFlight>,
+}
+
+impl WriteBody for TrackedBody {
+    fn write_body(&mut self, res: &mut dyn Write) -> io::Result<()> {
+        let result = match self.inner.as_mut() {
+            Some(body) => body.write_body(res),
+            None => Ok(()),
+        };
+        self.in_flight.take();
+        result
+    }
+}
+
+fn track_body(res: &mut Response, in_flight: InFlight) {
+    res.body = Some(Box::new(TrackedBody {
+        inner: res.body.take(),
+        in_flight: Some(in_flight),
+    }));
+}
+
+impl Shutdown {
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    // Stop taking requests and wait for the running ones, up to `timeout`.
+    // Returns how many were still running when we gave up.
+    pub fn drain(&self, timeout: Duration) -> usize {
+        self.draining.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + timeout;
+        while self.in_flight() > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        self.in_flight()
+    }
+}
+
+// Wraps the whole chain so every request is counted
+pub struct Tracked<H> {
+    handler: H,
+    state: Arc<Shutdown>,
+}
+
+impl<H: Handler> Tracked<H> {
+    pub fn new(handler: H) -> Self {
+        Tracked {
+            handler,
+            state: Arc::new(Shutdown::default()),
+        }
+    }
+
+    pub fn shutdown(&self) -> Arc<Shutdown> {
+        self.state.clone()
+    }
+}
+
+impl<H: Handler> Handler for Tracked<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        // Counted before looking at `draining`: drain() sets it before counting,
+        // so either it waits for this request or the request sees it set
+        let in_flight = InFlight::start(&self.state);
+        if self.state.draining.load(Ordering::SeqCst) {
+            let mut res =
+                Response::with((StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down\n"));
+            res.headers.insert(
+                headers::CONNECTION,
+                headers::HeaderValue::from_static("close"),
+            );
+            return Ok(res);
+        }
+        match self.handler.handle(req) {
+            Ok(mut res) => {
+                track_body(&mut res, in_flight);
+                Ok(res)
+            }
+            Err(mut err) => {
+                track_body(&mut err.response, in_flight);
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drain_waits_for_requests_in_flight() {
+        let state = Arc::new(Shutdown::default());
+        let in_flight = InFlight::start(&state);
+        let request = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(in_flight);
+        });
+        assert_eq!(state.drain(Duration::from_secs(5)), 0);
+        request.join().unwrap();
+    }
+
+    #[test]
+    fn drain_gives_up_after_timeout() {
+        let state = Arc::new(Shutdown::default());
+        let _in_flight = InFlight::start(&state);
+        assert_eq!(state.drain(Duration::from_millis(50)), 1);
+    }
+
+    #[test]
+    fn request_ends_once_body_is_written() {
+        let state = Arc::new(Shutdown::default());
+        let mut body = TrackedBody {
+            inner: Some(Box::new("hello".to_string())),
+            in_flight: Some(InFlight::start(&state)),
+        };
+        assert_eq!(state.in_flight(), 1);
+        let mut out = Vec::new();
+        body.write_body(&mut out).unwrap();
+        assert_eq!(out, b"hello");
+        assert_eq!(state.in_flight(), 0);
+    }
+
+    #[test]
+    fn unwritten_body_ends_request_when_dropped() {
+        let state = Arc::new(Shutdown::default());
+        let body = TrackedBody {
+            inner: None,
+            in_flight: Some(InFlight::start(&state)),
+        };
+        assert_eq!(state.in_flight(), 1);
+        drop(body);
+        assert_eq!(state.in_flight(), 0);
+    }
+}
@@ -0,0 +1,393 @@
+// Source map (v3) from the transpiled bundle back to the Rust source.
+//
+// The JS slice carries no location info, so the mapping is recovered here:
+// each JS entry is attributed to the `#[to_js]` / `#[js_type]` / `#[js_object]`
+// item defining the same name, and every line of the entry is matched,
+// in order, against the lines of that item's body.
+
+pub const RUST_SOURCE_PATH: &str = "src/main.rs";
+pub const RUST_SOURCE: &str = include_str!("main.rs");
+
+const TRANSPILE_ATTRS: &[&str] = &["#[to_js]", "#[js_type]", "#[js_object]"];
+
+// Identifiers common to both languages which say nothing about where a line came from
+const NOISE_WORDS: &[&str] = &[
+    "function",
+    "class",
+    "let",
+    "const",
+    "var",
+    "return",
+    "if",
+    "else",
+    "while",
+    "for",
+    "of",
+    "in",
+    "new",
+    "this",
+    "self",
+    "true",
+    "false",
+    "null",
+    "undefined",
+    "mut",
+    "fn",
+    "match",
+    "break",
+    "continue",
+    "constructor",
+    "static",
+];
+
+#[derive(Debug, Clone)]
+pub struct RustItem {
+    pub name: String,
+    pub attr: &'static str,
+    // Zero-based, inclusive range of source lines spanned by the item
+    pub first_line: usize,
+    pub last_line: usize,
+}
+
+// Find the transpiled items in the Rust source, skipping anything in block comments
+pub fn rust_items(source: &str) -> Vec<RustItem> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut items = Vec::new();
+    let mut in_comment = false;
+    let mut pending_attr: Option<&'static str> = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        if in_comment {
+            if trimmed.contains("*/") {
+                in_comment = false;
+            }
+            continue;
+        }
+        if trimmed.starts_with("/*") && !trimmed.contains("*/") {
+            in_comment = true;
+            continue;
+        }
+        if let Some(attr) = TRANSPILE_ATTRS.iter().find(|a| trimmed.starts_with(**a)) {
+            pending_attr = Some(attr);
+            continue;
+        }
+        let Some(attr) = pending_attr else {
+            continue;
+        };
+        if trimmed.starts_with("#[") || trimmed.starts_with("//") {
+            continue;
+        }
+        pending_attr = None;
+        if let Some(name) = item_name(trimmed) {
+            items.push(RustItem {
+                name,
+                attr,
+                first_line: i,
+                last_line: item_end(&lines, i),
+            });
+        }
+    }
+    items
+}
+
+// Name of the item declared on this line: `fn foo(`, `struct Foo {`, `impl Foo {`
+fn item_name(line: &str) -> Option<String> {
+    let mut words = line
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|w| !w.is_empty());
+    while let Some(word) = words.next() {
+        if matches!(word, "fn" | "struct" | "enum" | "impl") {
+            return words.next().map(|w| w.to_string());
+        }
+    }
+    None
+}
+
+// Last line of the item starting at `start`, found by brace matching.
+// Braces in string literals and line comments do not count.
+fn item_end(lines: &[&str], start: usize) -> usize {
+    let mut depth = 0i32;
+    let mut opened = false;
+    for (i, line) in lines.iter().enumerate().skip(start) {
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut prev = ' ';
+        for c in line.chars() {
+            if in_string {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match c {
+                '"' => in_string = true,
+                '/' if prev == '/' => break,
+                '{' => {
+                    depth += 1;
+                    opened = true;
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+            prev = c;
+        }
+        if opened && depth <= 0 {
+            return i;
+        }
+    }
+    lines.len().saturating_sub(1)
+}
+
+pub fn words(line: &str) -> impl Iterator<Item = &str> {
+    line.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|w| !w.is_empty() && !w.chars().next().unwrap().is_ascii_digit())
+}
+
+fn significant_words(line: &str) -> Vec<&str> {
+    words(line).filter(|w| !NOISE_WORDS.contains(w)).collect()
+}
+
+// Names defined at the top level of a JS entry: functions, classes, and prototype methods
+pub fn js_defined_names(chunk: &str) -> Vec<(String, bool)> {
+    let mut names = Vec::new();
+    for line in chunk.lines() {
+        let trimmed = line.trim_start();
+        for (prefix, is_class) in [("function ", false), ("class ", true)] {
+            if let Some(rest) = trimmed.strip_prefix(prefix)
+                && let Some(name) = words(rest).next()
+            {
+                names.push((name.to_string(), is_class));
+            }
+        }
+        if let Some((name, _)) = trimmed.split_once(".prototype.") {
+            names.push((name.trim().to_string(), false));
+        }
+    }
+    names
+}
+
+// The Rust item a JS entry was generated from
+fn item_for_chunk<'a>(chunk: &str, items: &'a [RustItem]) -> Option<&'a RustItem> {
+    for (name, is_class) in js_defined_names(chunk) {
+        let mut candidates = items.iter().filter(|item| item.name == name);
+        let preferred = if is_class {
+            "#[js_type]"
+        } else {
+            "#[js_object]"
+        };
+        let best = candidates
+            .clone()
+            .find(|item| item.attr == preferred)
+            .or_else(|| candidates.next());
+        if best.is_some() {
+            return best;
+        }
+    }
+    None
+}
+
+// Source line for each line of the chunk, matched monotonically within the item.
+// A line is looked for after the last one matched, so that `a + b` in a body is not
+// taken for the `a` of the signature; lines with no match stay on the last one.
+fn map_chunk_lines(chunk: &str, item: &RustItem, source_lines: &[&str]) -> Vec<usize> {
+    let mut cursor = item.first_line;
+    let mut next = item.first_line;
+    chunk
+        .split('\n')
+        .map(|js_line| {
+            let wanted = significant_words(js_line);
+            if let Some(first) = wanted.first() {
+                let found = (next..=item.last_line).find(|&i| {
+                    source_lines
+                        .get(i)
+                        .map(|l| words(l).any(|w| w == *first))
+                        .unwrap_or(false)
+                });
+                if let Some(i) = found {
+                    cursor = i;
+                    next = i + 1;
+                }
+            }
+            cursor
+        })
+        .collect()
+}
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn push_vlq(out: &mut String, value: i64) {
+    let mut v = if value < 0 {
+        ((-value) << 1) | 1
+    } else {
+        value << 1
+    };
+    loop {
+        let mut digit = v & 0b11111;
+        v >>= 5;
+        if v > 0 {
+            digit |= 0b100000;
+        }
+        out.push(BASE64[digit as usize] as char);
+        if v == 0 {
+            break;
+        }
+    }
+}
+
+// Build the source map for a bundle made of `header_lines` lines of preamble
+// followed by `chunks` joined with "\n". Lines of unattributed chunks stay unmapped.
+pub fn generate(file: &str, header_lines: usize, chunks: &[&str]) -> String {
+    let source_lines: Vec<&str> = RUST_SOURCE.lines().collect();
+    let items = rust_items(RUST_SOURCE);
+
+    let mut lines: Vec<Option<usize>> = vec![None; header_lines];
+    for chunk in chunks {
+        match item_for_chunk(chunk, &items) {
+            Some(item) => lines.extend(
+                map_chunk_lines(chunk, item, &source_lines)
+                    .into_iter()
+                    .map(Some),
+            ),
+            None => lines.extend(chunk.split('\n').map(|_| None)),
+        }
+    }
+
+    // Every segment is [generated column, source index, source line, source column],
+    // the last three relative to the previous segment
+    let mut mappings = String::new();
+    let mut prev_line = 0i64;
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            mappings.push(';');
+        }
+        if let Some(line) = line {
+            push_vlq(&mut mappings, 0);
+            push_vlq(&mut mappings, 0);
+            push_vlq(&mut mappings, *line as i64 - prev_line);
+            push_vlq(&mut mappings, 0);
+            prev_line = *line as i64;
+        }
+    }
+
+    serde_json::json!({
+        "version": 3,
+        "file": file,
+        "sources": [RUST_SOURCE_PATH],
+        "sourcesContent": [RUST_SOURCE],
+        "names": [],
+        "mappings": mappings,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vlq(value: i64) -> String {
+        let mut out = String::new();
+        push_vlq(&mut out, value);
+        out
+    }
+
+    // Source line of each generated line, from the mappings written by generate()
+    fn decode_lines(mappings: &str) -> Vec<Option<i64>> {
+        let mut line = 0;
+        mappings
+            .split(';')
+            .map(|segment| {
+                if segment.is_empty() {
+                    return None;
+                }
+                let mut fields = Vec::new();
+                let (mut value, mut shift) = (0i64, 0);
+                for c in segment.bytes() {
+                    let digit = BASE64.iter().position(|&b| b == c).unwrap() as i64;
+                    value |= (digit & 0b11111) << shift;
+                    shift += 5;
+                    if digit & 0b100000 == 0 {
+                        let magnitude = value >> 1;
+                        fields.push(if value & 1 == 1 {
+                            -magnitude
+                        } else {
+                            magnitude
+                        });
+                        (value, shift) = (0, 0);
+                    }
+                }
+                line += fields[2];
+                Some(line)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn vlq_encoding() {
+        assert_eq!(vlq(0), "A");
+        assert_eq!(vlq(1), "C");
+        assert_eq!(vlq(-1), "D");
+        assert_eq!(vlq(15), "e");
+        assert_eq!(vlq(16), "gB");
+        assert_eq!(vlq(-16), "hB");
+        assert_eq!(vlq(123), "2H");
+        assert_eq!(vlq(1000), "w+B");
+    }
+
+    #[test]
+    fn items_and_their_lines() {
+        let source = "\
+// #[to_js] in a comment
+#[to_js]
+fn add(a: i32, b: i32) -> i32 {
+    let s = \"}\";
+    a + b
+}
+
+/*
+#[to_js]
+fn hidden() {}
+*/
+#[js_type]
+#[derive(Debug)]
+struct Point {
+    x: i32,
+}
+";
+        let items = rust_items(source);
+        let found: Vec<_> = items
+            .iter()
+            .map(|i| (i.name.as_str(), i.attr, i.first_line, i.last_line))
+            .collect();
+        assert_eq!(
+            found,
+            [("add", "#[to_js]", 2, 5), ("Point", "#[js_type]", 13, 15)]
+        );
+
+        let chunk = "function add(a, b) {\n  let s = \"}\";\n  return a + b;\n}";
+        let item = item_for_chunk(chunk, &items).unwrap();
+        assert_eq!(item.name, "add");
+        let lines: Vec<&str> = source.lines().collect();
+        assert_eq!(map_chunk_lines(chunk, item, &lines), [2, 3, 4, 4]);
+    }
+
+    #[test]
+    fn generated_map_points_at_main_rs() {
+        let chunk = "function add(a, b) {\n  return a + b;\n}";
+        let map: serde_json::Value =
+            serde_json::from_str(&generate("app.js", 2, &[chunk, "let unattributed = 1;"]))
+                .unwrap();
+        assert_eq!(map["sources"][0], RUST_SOURCE_PATH);
+
+        let lines = decode_lines(map["mappings"].as_str().unwrap());
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[..2], [None, None]);
+        assert_eq!(lines[5], None);
+        let source_line = |i: usize| RUST_SOURCE.lines().nth(lines[i].unwrap() as usize).unwrap();
+        assert!(source_line(2).contains("fn add("));
+        assert_eq!(source_line(3).trim(), "a + b");
+    }
+}
@@ -0,0 +1,45 @@
+// Server-sent events (text/event-stream).
+//
+// Iron finishes every response, so instead of one endless stream each request
+// gets the events available now; the `retry:` field tells the browser's
+// EventSource how soon to reconnect for more.
+
+use std::fmt::Write;
+
+use iron::prelude::*;
+use iron::{StatusCode, headers, mime};
+
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+    // Dispatched as this event type; "message" when unset
+    pub event: Option<String>,
+    pub id: Option<String>,
+    pub data: String,
+}
+
+pub fn event_stream(retry_ms: u64, events: &[Event]) -> Response {
+    let mut body = format!("retry: {}\n\n", retry_ms);
+    for event in events {
+        if let Some(name) = &event.event {
+            let _ = writeln!(body, "event: {}", name);
+        }
+        if let Some(id) = &event.id {
+            let _ = writeln!(body, "id: {}", id);
+        }
+        // Multi-line data is sent as several data fields, which the browser joins again
+        for line in event.data.split('\n') {
+            let _ = writeln!(body, "data: {}", line);
+        }
+        body.push('\n');
+    }
+
+    let content_type = "text/event-stream; charset=utf-8"
+        .parse::<mime::Mime>()
+        .unwrap();
+    let mut res = Response::with((content_type, StatusCode::OK, body));
+    res.headers.insert(
+        headers::CACHE_CONTROL,
+        headers::HeaderValue::from_static("no-cache"),
+    );
+    res
+}
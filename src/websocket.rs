@@ -0,0 +1,163 @@
+// WebSocket chat for the push demos, on its own port since Iron cannot upgrade
+// connections. Every client is in the same room: a ChatMessage of kind "echo"
+// comes back to its sender only, one of kind "chat" goes to every client.
+// Messages are JSON, in the same ChatMessage type the transpiled client builds.
+
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::{Message, WebSocket};
+
+use crate::{ChatMessage, Person};
+
+// How long a connection waits for its client before passing on room messages
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Default)]
+struct Room {
+    next_id: AtomicU64,
+    clients: Mutex<Vec<(u64, Sender<String>)>>,
+}
+
+impl Room {
+    fn join(&self) -> (u64, Receiver<String>) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = mpsc::channel();
+        self.clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((id, tx));
+        (id, rx)
+    }
+
+    fn leave(&self, id: u64) {
+        self.clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|(client, _)| *client != id);
+    }
+
+    fn broadcast(&self, text: &str) {
+        self.clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|(_, tx)| tx.send(text.to_string()).is_ok());
+    }
+}
+
+fn server_message(kind: &str, text: &str) -> String {
+    let message = ChatMessage {
+        kind: kind.to_string(),
+        author: Person::new("server".to_string(), 0),
+        text: text.to_string(),
+    };
+    serde_json::to_string(&message).unwrap_or_default()
+}
+
+// What to send back to the client itself, if anything
+fn handle_text(room: &Room, text: &str) -> Option<String> {
+    match serde_json::from_str::<ChatMessage>(text) {
+        Ok(message) if message.kind == "echo" => Some(text.to_string()),
+        Ok(message) if message.kind == "chat" => {
+            room.broadcast(text);
+            None
+        }
+        Ok(message) => Some(server_message(
+            "error",
+            &format!("unknown message kind {:?}", message.kind),
+        )),
+        Err(e) => Some(server_message(
+            "error",
+            &format!("not a ChatMessage: {}", e),
+        )),
+    }
+}
+
+fn is_timeout(e: &tungstenite::Error) -> bool {
+    matches!(e, tungstenite::Error::Io(e)
+        if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut))
+}
+
+// Alternates between reading from the client, with a short timeout,
+// and forwarding what the room has for it
+fn run_client(mut socket: WebSocket<TcpStream>, room: &Room) {
+    let (id, inbox) = room.join();
+    let _ = socket.get_ref().set_read_timeout(Some(POLL_INTERVAL));
+    let _ = socket.send(Message::text(server_message(
+        "welcome",
+        &format!("you are client #{}", id),
+    )));
+
+    'connection: loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let sent = match handle_text(room, text.as_str()) {
+                    Some(reply) => socket.send(Message::text(reply)),
+                    None => Ok(()),
+                };
+                if sent.is_err() {
+                    break;
+                }
+            }
+            // Pings are answered and closes acknowledged by tungstenite itself
+            Ok(_) => {}
+            Err(e) if is_timeout(&e) => {}
+            Err(_) => break,
+        }
+        while let Ok(text) = inbox.try_recv() {
+            if socket.send(Message::text(text)).is_err() {
+                break 'connection;
+            }
+        }
+    }
+    room.leave(id);
+}
+
+// Start accepting WebSocket clients on `addr`, from pages served at `allowed_origins`.
+// The Origin check keeps other sites from using a visitor's browser to join the room.
+pub fn serve(addr: &str, allowed_origins: Vec<String>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let room = Arc::new(Room::default());
+    let allowed_origins = Arc::new(allowed_origins);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let room = room.clone();
+            let allowed_origins = allowed_origins.clone();
+            thread::spawn(move || {
+                let check_origin = |req: &Request, res: Response| {
+                    let origin = req
+                        .headers()
+                        .get("origin")
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or("");
+                    if allowed_origins
+                        .iter()
+                        .any(|allowed| allowed == "*" || allowed == origin)
+                    {
+                        Ok(res)
+                    } else {
+                        let mut err =
+                            ErrorResponse::new(Some(format!("origin {:?} is not allowed", origin)));
+                        *err.status_mut() = StatusCode::FORBIDDEN;
+                        Err(err)
+                    }
+                };
+                match tungstenite::accept_hdr(stream, check_origin) {
+                    Ok(socket) => run_client(socket, &room),
+                    Err(e) => eprintln!("⚠️  WebSocket handshake failed: {}", e),
+                }
+            });
+        }
+    });
+    Ok(())
+}